@@ -1,11 +1,20 @@
-use crate::errors::SourceError;
-use crate::parser::{AstNode, Block, NodeId, Pipeline};
-use crate::protocol::Command;
+use crate::errors::{DiagnosticConfig, DiagnosticLevel, Severity, SourceError};
+use crate::lexer::{lex, LexError, Token};
+use crate::parser::{AstNode, Block, BlockId, NodeId, Parser, Pipeline};
+use crate::protocol::{Command, Signature};
 use crate::resolver::{
     DeclId, Frame, NameBindings, ScopeId, TypeDecl, TypeDeclId, VarId, Variable,
 };
-use crate::typechecker::{TypeId, Types};
-use std::collections::HashMap;
+use crate::typechecker::{
+    render_type, Type, TypeId, TypeVar, Types, FORBIDDEN_TYPE, NONE_TYPE, UNKNOWN_TYPE,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::rc::Rc;
+
+/// Callback handed each recorded error (see [`Compiler::with_error_sink`])
+pub type ErrorSink = Rc<dyn Fn(&SourceError)>;
 
 pub struct RollbackPoint {
     idx_span_start: usize,
@@ -39,17 +48,243 @@ impl<T> Spanned<T> {
     }
 }
 
+/// One entry of a source map attached to a generated file
+///
+/// It maps all offsets starting at `start` (relative to the generated file's contents) to the
+/// origin file, until the start of the next entry (or the end of the file for the last entry).
+#[derive(Debug, Clone)]
+pub struct SourceMapEntry {
+    /// Offset into the generated file's contents where this mapping starts
+    pub start: usize,
+    /// Name of the origin file
+    pub origin_fname: String,
+    /// Offset into the origin file corresponding to `start`
+    pub origin_offset: usize,
+}
+
+impl SourceMapEntry {
+    pub fn new(start: usize, origin_fname: impl Into<String>, origin_offset: usize) -> Self {
+        Self {
+            start,
+            origin_fname: origin_fname.into(),
+            origin_offset,
+        }
+    }
+}
+
+/// Kind of a completion suggestion (see [`Compiler::completions_at`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Variable,
+    Command,
+    Field,
+    Flag,
+}
+
+/// One completion suggestion offered at a cursor position
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionItem {
+    pub label: String,
+    pub kind: CompletionKind,
+    /// Extra information shown next to the label (e.g., a field's initializer text)
+    pub detail: Option<String>,
+}
+
+/// Kind of a symbol in the document outline (see [`Compiler::document_symbols`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Module,
+    Constant,
+}
+
+/// One symbol of the document outline, e.g., a command definition or a top-level variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Span of the whole definition
+    pub span: Span,
+    /// Span of just the name, for cursor selection
+    pub selection_span: Span,
+    /// Symbols defined inside this one (e.g., commands nested in a command's body)
+    pub children: Vec<DocumentSymbol>,
+}
+
+/// Information about one user-declared command (see [`Compiler::declared_commands`])
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandInfo {
+    pub name: String,
+    /// Source text of the parameter list, e.g. `[a: int, --verbose]`; empty for aliases
+    pub signature: String,
+    /// `#` comment lines directly above the declaration, joined into a single line
+    pub doc_comment: Option<String>,
+    /// Attribute syntax is not parsed yet; always empty for now
+    pub attributes: Vec<String>,
+    /// Module exports are not parsed yet; always false for now
+    pub exported: bool,
+    /// Span of the whole declaration
+    pub span: Span,
+}
+
+/// Which argument slot of a call an offset falls into (see [`Compiler::call_argument_at`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgPosition {
+    /// Inside the n-th positional argument (0-based)
+    Positional(usize),
+    /// Inside a flag itself; holds the flag's node
+    Flag(NodeId),
+    /// Inside the value following a flag; holds the flag's node
+    FlagValue(NodeId),
+}
+
+/// One entry of the linear pre-order view produced by [`Compiler::flatten`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatNode {
+    /// Nesting depth below the root node
+    pub depth: usize,
+    /// Name of the AST node variant, without its payload
+    pub kind: String,
+    /// Source text of leaf nodes that carry a literal (numbers, strings, names)
+    pub text: Option<String>,
+}
+
+/// What a name resolved to (see [`Compiler::probe_resolution`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Variable(VarId),
+    Decl(DeclId),
+    /// A command built into the language, without a user-visible declaration
+    Builtin,
+    Unresolved,
+}
+
+/// The owned, serializable pieces of a finished compilation (see [`Compiler::into_parts`])
+///
+/// Excludes `decls` (a `Vec<Box<dyn Command>>` cannot be serialized) and the name-binding
+/// maps that reference it, so a compiler reconstructed via [`Compiler::from_parts`] is
+/// read-only: `decls` must be re-attached (by re-running resolution) for full functionality.
+pub struct CompilerParts {
+    pub ast_nodes: Vec<AstNode>,
+    pub spans: Vec<Span>,
+    pub blocks: Vec<Block>,
+    pub pipelines: Vec<Pipeline>,
+    pub source: Vec<u8>,
+    pub file_offsets: Vec<(String, usize, usize)>,
+    pub errors: Vec<SourceError>,
+}
+
+/// How span offsets are expressed in exported parts (see [`Compiler::into_parts_with`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanMode {
+    /// Byte offsets into the source, as the compiler uses internally
+    Bytes,
+    /// Character (code point) offsets, for clients that count code points rather than bytes
+    Chars,
+}
+
+/// Identifier of a caller-defined literal type (see [`Compiler::register_literal_suffix`]).
+/// The compiler does not interpret the id; it only threads it through to the resulting type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CustomTypeId(pub usize);
+
+/// A command built into the language, recognized by name
+///
+/// Builtins have no entry in the decl table, so every name-based query —
+/// [`Compiler::probe_resolution`], [`Compiler::builtin_signature`] and the typechecker's
+/// higher-order handling — reads this one table rather than keeping its own list.
+pub(crate) struct BuiltinCommand {
+    pub(crate) name: &'static [u8],
+    pub(crate) positional: &'static [&'static str],
+    pub(crate) flags: &'static [&'static str],
+    /// Whether the command threads its pipeline input's element type into a closure argument
+    pub(crate) higher_order: bool,
+}
+
+pub(crate) const BUILTIN_COMMANDS: &[BuiltinCommand] = &[
+    BuiltinCommand {
+        name: b"each",
+        positional: &["closure"],
+        flags: &[],
+        higher_order: true,
+    },
+    BuiltinCommand {
+        name: b"where",
+        positional: &["closure"],
+        flags: &[],
+        higher_order: true,
+    },
+    BuiltinCommand {
+        name: b"reduce",
+        positional: &["closure"],
+        flags: &["fold"],
+        higher_order: true,
+    },
+    BuiltinCommand {
+        name: b"complete",
+        positional: &[],
+        flags: &[],
+        higher_order: false,
+    },
+];
+
+pub(crate) fn builtin_command(name: &[u8]) -> Option<&'static BuiltinCommand> {
+    BUILTIN_COMMANDS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Result of a read-only resolution query (see [`Compiler::probe_resolution`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolutionInfo {
+    pub resolution: Resolution,
+    /// Scope frame in which the name was found, if any
+    pub scope: Option<ScopeId>,
+}
+
 #[derive(Clone)]
 pub struct Compiler {
     // Core information, indexed by NodeId:
     pub spans: Vec<Span>,
     pub ast_nodes: Vec<AstNode>,
     pub node_types: Vec<TypeId>,
+    /// Type table retained from the typecheck pass, indexed by TypeId; empty before typechecking
+    pub types: Vec<Type>,
+    /// Record fields for `Type::Record`, indexed by RecordTypeId
+    pub record_types: Vec<Vec<(NodeId, TypeId)>>,
+    /// Member sets for `Type::OneOf`, indexed by OneOfId
+    pub oneof_types: Vec<HashSet<TypeId>>,
+    /// Member sets for `Type::AllOf`, indexed by AllOfId
+    pub allof_types: Vec<HashSet<TypeId>>,
+    /// Type variables, indexed by TypeVarId
+    pub type_vars: Vec<TypeVar>,
     // node_lifetimes: Vec<AllocationLifetime>,
     pub blocks: Vec<Block>,       // Blocks, indexed by BlockId
     pub pipelines: Vec<Pipeline>, // Pipelines, indexed by PipelineId
     pub source: Vec<u8>,
     pub file_offsets: Vec<(String, usize, usize)>, // fname, start, end
+    /// Optional source maps of generated files, keyed by file name (see [`SourceMapEntry`])
+    pub source_maps: HashMap<String, Vec<SourceMapEntry>>,
+    /// Optional limit on the total size of the source in bytes, checked by add_file()
+    pub max_source_bytes: Option<usize>,
+    /// Optional wall-clock bound on parsing, checked cooperatively at statement boundaries
+    /// (see [`Compiler::with_max_parse_time`])
+    pub max_parse_time: Option<Duration>,
+    /// Optional working directory used as a fallback when resolving relative `use`/`source`
+    /// paths (see [`Compiler::resolve_module_path`])
+    pub working_dir: Option<PathBuf>,
+    /// Whether add_file() normalizes CRLF/CR line endings to LF (see
+    /// [`Compiler::set_normalize_line_endings`])
+    pub normalize_line_endings: bool,
+    /// Whether the parser eagerly inlines `source`d files' statements into the enclosing
+    /// block (see [`Compiler::set_inline_sourced_files`])
+    pub inline_sourced_files: bool,
+    /// For files added with line-ending normalization: positions (relative to the file's
+    /// normalized contents) of newlines that replaced a two-byte CRLF sequence, keyed by
+    /// file name. Used to report positions in terms of the original contents.
+    pub line_ending_shifts: HashMap<String, Vec<usize>>,
+    /// Caller-registered numeric literal suffixes and the custom type ids they map to (see
+    /// [`Compiler::register_literal_suffix`])
+    pub custom_suffixes: Vec<(Vec<u8>, CustomTypeId)>,
 
     // name bindings:
     /// All scope frames ever entered, indexed by ScopeId
@@ -80,6 +315,17 @@ pub struct Compiler {
     // Use/def
     // pub call_resolution: HashMap<NodeId, CallTarget>,
     pub errors: Vec<SourceError>,
+    /// Optional callback invoked on each recorded error, in addition to accumulation in
+    /// `errors` (see [`Compiler::with_error_sink`])
+    pub error_sink: Option<ErrorSink>,
+    /// Per-code severity policy consulted by [`Compiler::push_error`] (see
+    /// [`DiagnosticConfig`])
+    pub diagnostic_config: DiagnosticConfig,
+
+    /// Nodes generated by the parser rather than written by the user (e.g., an implicit $in or
+    /// an error placeholder at the end of file). Tracked in a side set so that the flag does not
+    /// bloat AstNode itself.
+    pub synthetic_nodes: HashSet<NodeId>,
 }
 
 impl Default for Compiler {
@@ -94,10 +340,23 @@ impl Compiler {
             spans: vec![],
             ast_nodes: vec![],
             node_types: vec![],
+            types: vec![],
+            record_types: vec![],
+            oneof_types: vec![],
+            allof_types: vec![],
+            type_vars: vec![],
             blocks: vec![],
             pipelines: vec![],
             source: vec![],
             file_offsets: vec![],
+            source_maps: HashMap::new(),
+            max_source_bytes: None,
+            max_parse_time: None,
+            inline_sourced_files: false,
+            working_dir: None,
+            normalize_line_endings: false,
+            line_ending_shifts: HashMap::new(),
+            custom_suffixes: vec![],
 
             scope: vec![],
             scope_stack: vec![],
@@ -115,6 +374,10 @@ impl Compiler {
 
             // call_resolution: HashMap::new(),
             errors: vec![],
+            error_sink: None,
+            diagnostic_config: DiagnosticConfig::default(),
+
+            synthetic_nodes: HashSet::new(),
         }
     }
 
@@ -138,10 +401,7 @@ impl Compiler {
                 ast_node,
                 AstNode::Name | AstNode::Variable | AstNode::Int | AstNode::Float | AstNode::String
             ) {
-                result.push_str(&format!(
-                    " \"{}\"",
-                    String::from_utf8_lossy(self.get_span_contents(NodeId(idx)))
-                ));
+                result.push_str(&format!(" \"{}\"", self.span_text_lossy(self.spans[idx])));
             }
 
             result.push('\n');
@@ -160,6 +420,41 @@ impl Compiler {
         result
     }
 
+    /// Register a callback that is handed each error as it is recorded, in push order
+    ///
+    /// Errors still accumulate in `errors` as usual; the sink lets a caller (e.g. an LSP
+    /// streaming diagnostics) act on them without waiting for the compilation to finish.
+    /// When no sink is registered, recording an error costs a single `Option` check.
+    pub fn with_error_sink(mut self, sink: impl Fn(&SourceError) + 'static) -> Self {
+        self.error_sink = Some(Rc::new(sink));
+        self
+    }
+
+    /// Set the per-code severity policy consulted by [`Compiler::push_error`]
+    pub fn with_diagnostic_config(mut self, config: DiagnosticConfig) -> Self {
+        self.diagnostic_config = config;
+        self
+    }
+
+    /// Record an error, notifying the error sink if one is registered
+    ///
+    /// Errors carrying a diagnostic code are first run through the [`DiagnosticConfig`]:
+    /// a code set to off is dropped, one mapped to a severity is reported at that
+    /// severity instead of its default.
+    pub fn push_error(&mut self, mut error: SourceError) {
+        if let Some(code) = error.code {
+            match self.diagnostic_config.level(code) {
+                Some(DiagnosticLevel::Off) => return,
+                Some(DiagnosticLevel::Report(severity)) => error.severity = severity,
+                None => {}
+            }
+        }
+        if let Some(sink) = &self.error_sink {
+            sink(&error);
+        }
+        self.errors.push(error);
+    }
+
     pub fn merge_name_bindings(&mut self, name_bindings: NameBindings) {
         self.scope.extend(name_bindings.scope);
         self.scope_stack.extend(name_bindings.scope_stack);
@@ -170,21 +465,434 @@ impl Compiler {
         self.decls.extend(name_bindings.decls);
         self.decl_nodes.extend(name_bindings.decl_nodes);
         self.decl_resolution.extend(name_bindings.decl_resolution);
-        self.errors.extend(name_bindings.errors);
+        for error in name_bindings.errors {
+            self.push_error(error);
+        }
     }
 
     pub fn merge_types(&mut self, types: Types) {
         self.node_types.extend(types.node_types);
-        self.errors.extend(types.errors);
+        // each typecheck pass rebuilds the tables from scratch; keep the latest ones
+        self.types = types.types;
+        self.record_types = types.record_types;
+        self.oneof_types = types.oneof_types;
+        self.allof_types = types.allof_types;
+        self.type_vars = types.type_vars;
+        for error in types.errors {
+            self.push_error(error);
+        }
+    }
+
+    /// Decompose a finished compiler into its owned, serializable parts
+    ///
+    /// Useful for persisting a parse result to disk (e.g. with serde) and reloading it later
+    /// via [`Compiler::from_parts`] without re-running builtins registration.
+    pub fn into_parts(self) -> CompilerParts {
+        self.into_parts_with(SpanMode::Bytes)
+    }
+
+    /// Like [`Compiler::into_parts`], with control over how span offsets are expressed
+    ///
+    /// With [`SpanMode::Chars`] every span (and every file boundary in `file_offsets`) is
+    /// converted from byte offsets to character offsets, for editor clients that count code
+    /// points. Files are stored concatenated, so a single index over the whole source maps
+    /// offsets for every file at once. Parts exported this way are for consumption only;
+    /// feeding them back into [`Compiler::from_parts`] requires byte offsets.
+    pub fn into_parts_with(self, span_mode: SpanMode) -> CompilerParts {
+        let mut parts = CompilerParts {
+            ast_nodes: self.ast_nodes,
+            spans: self.spans,
+            blocks: self.blocks,
+            pipelines: self.pipelines,
+            source: self.source,
+            file_offsets: self.file_offsets,
+            errors: self.errors,
+        };
+
+        if span_mode == SpanMode::Chars {
+            // char offset at each byte offset: UTF-8 continuation bytes do not start a char
+            let mut byte_to_char = Vec::with_capacity(parts.source.len() + 1);
+            let mut chars = 0;
+            for byte in &parts.source {
+                byte_to_char.push(chars);
+                if byte & 0b1100_0000 != 0b1000_0000 {
+                    chars += 1;
+                }
+            }
+            byte_to_char.push(chars);
+
+            let to_char = |offset: usize| byte_to_char[offset.min(byte_to_char.len() - 1)];
+            for span in &mut parts.spans {
+                span.start = to_char(span.start);
+                span.end = to_char(span.end);
+            }
+            for (_, start, end) in &mut parts.file_offsets {
+                *start = to_char(*start);
+                *end = to_char(*end);
+            }
+        }
+
+        parts
+    }
+
+    /// Reconstruct a read-only compiler from the parts of a previous compilation
+    ///
+    /// The result can be queried and displayed but has no name bindings; `decls` must be
+    /// re-attached (by re-running resolution) for full functionality.
+    pub fn from_parts(parts: CompilerParts) -> Self {
+        Self {
+            ast_nodes: parts.ast_nodes,
+            spans: parts.spans,
+            blocks: parts.blocks,
+            pipelines: parts.pipelines,
+            source: parts.source,
+            file_offsets: parts.file_offsets,
+            errors: parts.errors,
+            ..Self::new()
+        }
+    }
+
+    /// Set a limit on the total size of the source in bytes
+    ///
+    /// Intended as a protection for tools that accept untrusted input. None (the default) means
+    /// no limit.
+    pub fn set_max_source_bytes(&mut self, limit: Option<usize>) {
+        self.max_source_bytes = limit;
+    }
+
+    /// Set a wall-clock bound on parsing
+    ///
+    /// Intended as a protection for services that parse untrusted input. The parser checks the
+    /// deadline cooperatively at statement boundaries (no threads or signals involved); on
+    /// expiry it records a "parse timed out" error and stops, leaving the partial tree usable.
+    pub fn with_max_parse_time(mut self, limit: Duration) -> Self {
+        self.max_parse_time = Some(limit);
+        self
+    }
+
+    /// Register a numeric literal suffix so that e.g. `5px` parses into a custom literal tagged
+    /// with `type_id`
+    ///
+    /// Suffixes built into the lexer (e.g. float exponents) take precedence because such literals
+    /// never reach the parser as barewords. An unregistered suffix still parses as a bare word.
+    pub fn register_literal_suffix(&mut self, suffix: &[u8], type_id: CustomTypeId) {
+        self.custom_suffixes.push((suffix.to_vec(), type_id));
+    }
+
+    /// Look up the custom type id registered for a numeric literal suffix, if any
+    pub fn custom_literal_suffix(&self, suffix: &[u8]) -> Option<CustomTypeId> {
+        self.custom_suffixes
+            .iter()
+            .find(|(registered, _)| registered.as_slice() == suffix)
+            .map(|(_, type_id)| *type_id)
+    }
+
+    /// Hash the exact bytes of a previously added file, or None if no such file
+    ///
+    /// Identical hashes imply identical parse output (given the same dialect/config), so a
+    /// build tool caching per-file parse results can skip reparsing a file whose hash has not
+    /// changed. The hash is stable across runs and platforms (FNV-1a rather than the standard
+    /// library's randomly seeded default hasher).
+    pub fn source_hash(&self, fname: &str) -> Option<u64> {
+        let (_, start, end) = self
+            .file_offsets
+            .iter()
+            .find(|(name, _, _)| name == fname)?;
+
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in &self.source[*start..*end] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100_0000_01b3);
+        }
+        Some(hash)
+    }
+
+    /// Set the working directory used as a fallback when resolving relative `use`/`source` paths
+    pub fn set_working_dir(&mut self, path: Option<PathBuf>) {
+        self.working_dir = path;
+    }
+
+    /// Make the parser eagerly inline `source`d files' statements into the enclosing block
+    ///
+    /// The sourced file is added via add_file(), so diagnostics in the inlined statements
+    /// still point at it. Off by default.
+    pub fn set_inline_sourced_files(&mut self, inline: bool) {
+        self.inline_sourced_files = inline;
+    }
+
+    /// Resolve a `use`/`source` path mentioned in `importer_fname`
+    ///
+    /// Relative paths are first resolved relative to the importing file's directory. If the file
+    /// is not found there and a working directory is configured (see [`Compiler::set_working_dir`]),
+    /// it is resolved relative to the working directory instead. If the file exists in neither
+    /// place, the error lists all attempted locations.
+    pub fn resolve_module_path(
+        &self,
+        importer_fname: &str,
+        path: &str,
+    ) -> Result<PathBuf, String> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return if path.exists() {
+                Ok(path.to_path_buf())
+            } else {
+                Err(format!("file not found: {}", path.display()))
+            };
+        }
+
+        let mut attempted = Vec::new();
+
+        // resolve relative to the importing file
+        let importer_dir = Path::new(importer_fname)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let candidate = importer_dir.join(path);
+        if candidate.exists() {
+            return Ok(candidate);
+        }
+        attempted.push(candidate);
+
+        // fall back to the working directory
+        if let Some(working_dir) = &self.working_dir {
+            let candidate = working_dir.join(path);
+            if candidate.exists() {
+                return Ok(candidate);
+            }
+            attempted.push(candidate);
+        }
+
+        Err(format!(
+            "file not found: {}; attempted {}",
+            path.display(),
+            attempted
+                .iter()
+                .map(|attempt| attempt.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+
+    /// Set whether add_file() normalizes CRLF/CR line endings to LF
+    ///
+    /// When enabled, the compiler keeps an offset map per normalized file so that positions can
+    /// still be reported in terms of the original contents (see
+    /// [`Compiler::original_position`]).
+    pub fn set_normalize_line_endings(&mut self, enable: bool) {
+        self.normalize_line_endings = enable;
+    }
+
+    /// Map a global span offset to the file it belongs to and the offset into the file's
+    /// original (pre-normalization) contents
+    ///
+    /// For files added without line-ending normalization the returned offset equals the
+    /// file-relative offset.
+    pub fn original_position(&self, offset: usize) -> Option<(&str, usize)> {
+        let (fname, file_start, _) = self
+            .file_offsets
+            .iter()
+            .find(|(_, start, end)| offset >= *start && offset < *end)?;
+
+        let rel_offset = offset - file_start;
+        let shift = self.line_ending_shifts.get(fname).map_or(0, |positions| {
+            positions.iter().take_while(|pos| **pos < rel_offset).count()
+        });
+
+        Some((fname, rel_offset + shift))
     }
 
-    pub fn add_file(&mut self, fname: &str, contents: &[u8]) {
+    /// Add a file's contents to the compiler's source
+    ///
+    /// Returns false and records an error if adding the contents would exceed max_source_bytes;
+    /// in that case, the contents are not added and should not be parsed.
+    pub fn add_file(&mut self, fname: &str, contents: &[u8]) -> bool {
+        let normalized;
+        let (contents, crlf_positions): (&[u8], Vec<usize>) = if self.normalize_line_endings {
+            let (normalized_contents, crlf_positions) = normalize_line_endings(contents);
+            normalized = normalized_contents;
+            (&normalized, crlf_positions)
+        } else {
+            (contents, vec![])
+        };
+
+        if let Some(limit) = self.max_source_bytes {
+            if self.source.len() + contents.len() > limit {
+                self.push_error(SourceError {
+                    message: format!(
+                        "source exceeds maximum size of {limit} bytes ({fname})"
+                    ),
+                    // there is no node to attach the error to
+                    node_id: NodeId(0),
+                    severity: Severity::Error,
+                    code: None,
+                });
+                return false;
+            }
+        }
+
         let span_offset = self.source.len();
 
         self.file_offsets
             .push((fname.to_string(), span_offset, span_offset + contents.len()));
 
         self.source.extend_from_slice(contents);
+
+        if !crlf_positions.is_empty() {
+            self.line_ending_shifts
+                .insert(fname.to_string(), crlf_positions);
+        }
+
+        true
+    }
+
+    /// Same as add_file() but places the contents at a fixed base offset in span space
+    ///
+    /// Intended for editors sending just a fragment (e.g. a selection for a scratch
+    /// evaluation) that originates at a known offset in a larger document: spans of the
+    /// parsed fragment come out relative to the original document, so the caller does not
+    /// have to re-shift every span. Lex the contents with `base_offset` as the span offset.
+    /// The gap up to `base_offset` is padded with spaces and belongs to no file; a
+    /// `base_offset` below the current [`Compiler::span_offset`] is clamped to it.
+    pub fn add_fragment(&mut self, fname: &str, contents: &[u8], base_offset: usize) -> bool {
+        if base_offset > self.source.len() {
+            // the padding counts towards the size limit like any other source bytes
+            if let Some(limit) = self.max_source_bytes {
+                if base_offset + contents.len() > limit {
+                    self.push_error(SourceError {
+                        message: format!(
+                            "source exceeds maximum size of {limit} bytes ({fname})"
+                        ),
+                        // there is no node to attach the error to
+                        node_id: NodeId(0),
+                        severity: Severity::Error,
+                        code: None,
+                    });
+                    return false;
+                }
+            }
+            self.source.resize(base_offset, b' ');
+        }
+        self.add_file(fname, contents)
+    }
+
+    /// Rename a previously added file without reparsing
+    ///
+    /// Intended for editors renaming a buffer: the contents and spans stay as they are,
+    /// only the name recorded for them changes. Returns false if no file named `old` was
+    /// added.
+    pub fn rename_file(&mut self, old: &str, new: &str) -> bool {
+        let mut found = false;
+        for (fname, _, _) in self.file_offsets.iter_mut() {
+            if fname == old {
+                *fname = new.to_string();
+                found = true;
+            }
+        }
+        if let Some(shifts) = self.line_ending_shifts.remove(old) {
+            self.line_ending_shifts.insert(new.to_string(), shifts);
+        }
+        found
+    }
+
+    /// Parse the given source as exactly one expression (expression mode)
+    ///
+    /// Intended for embedding Nushell expressions, e.g., a config value or a `--expr` flag.
+    /// Trailing tokens beyond the expression are an error; trailing whitespace and comments are
+    /// fine. Returns the expression's node, or the errors encountered.
+    pub fn parse_expression(&mut self, source: &[u8]) -> Result<NodeId, Vec<SourceError>> {
+        let span_offset = self.span_offset();
+        let num_errors = self.errors.len();
+        // if the contents are rejected (max_source_bytes), parsing them would produce spans
+        // past the end of the retained source
+        if !self.add_file("<expression>", source) {
+            return Err(self.errors[num_errors..].to_vec());
+        }
+
+        let (tokens, err) = lex(source, span_offset);
+        if let Err(err) = err {
+            match err.item {
+                LexError::UnterminatedString => {
+                    // synthetic garbage nodes carry the spans, so diagnostics can point at the
+                    // end of input with the opening quote as a related label
+                    let eof = self.push_node(AstNode::Garbage);
+                    self.spans.push(Span::new(err.span.end, err.span.end));
+                    self.push_error(SourceError {
+                        message: "unterminated double-quoted string".to_string(),
+                        node_id: eof,
+                        severity: Severity::Error,
+                        code: None,
+                    });
+
+                    let quote = self.push_node(AstNode::Garbage);
+                    self.spans.push(Span::new(err.span.start, err.span.start + 1));
+                    self.push_error(SourceError {
+                        message: "string opened here".to_string(),
+                        node_id: quote,
+                        severity: Severity::Note,
+                        code: None,
+                    });
+                }
+                item => {
+                    self.push_error(SourceError {
+                        message: format!("error lexing: {item:?}"),
+                        // there is no node to attach the error to
+                        node_id: NodeId(0),
+                        severity: Severity::Error,
+                        code: None,
+                    });
+                }
+            }
+            return Err(self.errors[num_errors..].to_vec());
+        }
+
+        let compiler = std::mem::take(self);
+        let (compiler, node_id) = Parser::new(compiler, tokens).parse_expression();
+        *self = compiler;
+
+        if self.errors.len() > num_errors {
+            Err(self.errors[num_errors..].to_vec())
+        } else {
+            Ok(node_id)
+        }
+    }
+
+    /// Same as add_file() but attaches a source map for generated content
+    ///
+    /// Tools that generate Nushell code can use the map to attribute spans of the generated
+    /// contents back to their original locations. Files added via plain add_file() are unaffected.
+    /// Returns false and records an error, attaching no map, if add_file() rejects the contents.
+    pub fn add_file_with_source_map(
+        &mut self,
+        fname: &str,
+        contents: &[u8],
+        source_map: Vec<SourceMapEntry>,
+    ) -> bool {
+        if !self.add_file(fname, contents) {
+            return false;
+        }
+        self.source_maps.insert(fname.to_string(), source_map);
+        true
+    }
+
+    /// Map a global span offset back to its origin (file, offset), if the offset belongs to a
+    /// generated file with an attached source map
+    pub fn origin_location(&self, offset: usize) -> Option<(&str, usize)> {
+        let (fname, file_start, _) = self
+            .file_offsets
+            .iter()
+            .find(|(_, start, end)| offset >= *start && offset < *end)?;
+
+        let source_map = self.source_maps.get(fname)?;
+        let rel_offset = offset - file_start;
+        let entry = source_map
+            .iter()
+            .take_while(|entry| entry.start <= rel_offset)
+            .last()?;
+
+        Some((
+            &entry.origin_fname,
+            entry.origin_offset + (rel_offset - entry.start),
+        ))
     }
 
     pub fn span_offset(&self) -> usize {
@@ -205,58 +913,2394 @@ impl Compiler {
         NodeId(self.ast_nodes.len() - 1)
     }
 
-    pub fn get_rollback_point(&self, token_pos: usize) -> RollbackPoint {
-        RollbackPoint {
-            idx_span_start: self.spans.len(),
-            idx_nodes: self.ast_nodes.len(),
-            idx_errors: self.errors.len(),
-            idx_blocks: self.blocks.len(),
-            token_pos,
-        }
+    /// Mark a node as generated by the parser (not written by the user)
+    pub fn set_synthetic(&mut self, node_id: NodeId) {
+        self.synthetic_nodes.insert(node_id);
     }
 
-    pub fn apply_compiler_rollback(&mut self, rbp: RollbackPoint) -> usize {
-        self.blocks.truncate(rbp.idx_blocks);
-        self.ast_nodes.truncate(rbp.idx_nodes);
-        self.errors.truncate(rbp.idx_errors);
-        self.spans.truncate(rbp.idx_span_start);
+    /// Check whether a node was generated by the parser rather than written by the user
+    pub fn is_synthetic(&self, node_id: NodeId) -> bool {
+        self.synthetic_nodes.contains(&node_id)
+    }
 
-        rbp.token_pos
+    /// Variables declared directly in the given block's scope frame, ordered by VarId
+    ///
+    /// Does not include variables of nested blocks, nor variables captured from outer scopes.
+    pub fn block_locals(&self, block_id: BlockId) -> Vec<VarId> {
+        let mut locals: Vec<VarId> = self
+            .scope
+            .iter()
+            .filter(|frame| {
+                matches!(self.ast_nodes[frame.node_id.0], AstNode::Block(id) if id == block_id)
+            })
+            .flat_map(|frame| frame.variables.values())
+            .filter_map(|name_node| self.var_resolution.get(name_node).copied())
+            .collect();
+        locals.sort_by_key(|var_id| var_id.0);
+        locals
     }
 
-    /// Get span of node
-    pub fn get_span(&self, node_id: NodeId) -> Span {
-        *self
-            .spans
-            .get(node_id.0)
-            .expect("internal error: missing span of node")
+    /// Raw token under the given source offset, for editor features like "select word"
+    ///
+    /// Re-lexes the file owning the offset. A token covers its span inclusively here: if the
+    /// offset falls exactly on the boundary between two tokens, the preceding token is preferred.
+    /// Returns None if the offset is not covered by any token (e.g., inside a whitespace run,
+    /// which the lexer skips).
+    pub fn token_at_offset(&self, offset: usize) -> Option<Spanned<Token>> {
+        let (_, file_start, file_end) = self
+            .file_offsets
+            .iter()
+            .find(|(_, start, end)| offset >= *start && offset <= *end)?;
+
+        let contents = &self.source[*file_start..*file_end];
+        let (mut tokens, _) = lex(contents, *file_start);
+        tokens.set_pos(0);
+
+        loop {
+            let (token, span) = tokens.peek();
+            if token == Token::Eof || span.start > offset {
+                return None;
+            }
+            if span.start <= offset && offset <= span.end {
+                return Some(Spanned::new(token, span));
+            }
+            tokens.advance();
+        }
     }
 
-    /// Get the source contents of a span of a node
-    pub fn get_span_contents(&self, node_id: NodeId) -> &[u8] {
+    /// Group the errors by the file their span belongs to, for per-file reporting
+    ///
+    /// Errors whose span cannot be attributed to any added file are grouped under a synthetic
+    /// "<unknown>" key.
+    pub fn errors_by_file(&self) -> BTreeMap<String, Vec<&SourceError>> {
+        let mut result: BTreeMap<String, Vec<&SourceError>> = BTreeMap::new();
+        for error in &self.errors {
+            let fname = self
+                .spans
+                .get(error.node_id.0)
+                .and_then(|span| {
+                    self.file_offsets
+                        .iter()
+                        .find(|(_, start, end)| span.start >= *start && span.start < *end)
+                })
+                .map_or("<unknown>", |(fname, _, _)| fname.as_str());
+            result.entry(fname.to_string()).or_default().push(error);
+        }
+        result
+    }
+
+    /// Visit every recorded error together with its source line and resolved position
+    ///
+    /// For each error the callback receives the error itself, the text of the line its span
+    /// starts on (without the trailing newline), and the 1-based (line, column) of the span
+    /// start. This packages the span math a custom diagnostic renderer would otherwise have to
+    /// redo. Errors whose span lies outside the stored source are reported with an empty line
+    /// and position (1, 1).
+    pub fn for_each_error(&self, mut f: impl FnMut(&SourceError, &str, (usize, usize))) {
+        for error in &self.errors {
+            let start = self
+                .spans
+                .get(error.node_id.0)
+                .map_or(usize::MAX, |span| span.start);
+            if start > self.source.len() {
+                f(error, "", (1, 1));
+                continue;
+            }
+
+            let line_start = self.source[..start]
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |pos| pos + 1);
+            let line_end = self.source[start..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(self.source.len(), |pos| start + pos);
+            let line = self.source[..start].iter().filter(|b| **b == b'\n').count() + 1;
+            let col = start - line_start + 1;
+
+            let text = String::from_utf8_lossy(&self.source[line_start..line_end]);
+            f(error, &text, (line, col));
+        }
+    }
+
+    /// Byte length of the node's span, i.e. the amount of source text the subtree covers
+    ///
+    /// Together with [`Compiler::subtree_node_count`] this gives tools a cheap size measure,
+    /// e.g. for deciding whether a block is large enough to suggest extracting a command.
+    pub fn subtree_byte_len(&self, node_id: NodeId) -> usize {
         let span = self.get_span(node_id);
-        self.source
-            .get(span.start..span.end)
-            .expect("internal error: missing source of span")
+        span.end - span.start
     }
 
-    /// Get the source contents of a span
-    pub fn get_span_contents_manual(&self, span_start: usize, span_end: usize) -> &[u8] {
-        self.source
-            .get(span_start..span_end)
-            .expect("internal error: missing source of span")
+    /// Number of nodes in the subtree rooted at the given node, including the node itself
+    pub fn subtree_node_count(&self, node_id: NodeId) -> usize {
+        let mut count = 0;
+        let mut stack = vec![node_id];
+        while let Some(node_id) = stack.pop() {
+            count += 1;
+            // blocks and pipelines reference their contents via BlockId/PipelineId rather than
+            // through children()
+            match &self.ast_nodes[node_id.0] {
+                AstNode::Block(block_id) => stack.extend(&self.blocks[block_id.0].nodes),
+                AstNode::Pipeline(pipeline_id) => {
+                    stack.extend(&self.pipelines[pipeline_id.0].nodes)
+                }
+                node => stack.extend(node.children()),
+            }
+        }
+        count
     }
 
-    /// Get the source contents of a node
-    pub fn node_as_str(&self, node_id: NodeId) -> &str {
-        std::str::from_utf8(self.get_span_contents(node_id))
-            .expect("internal error: expected utf8 string")
+    /// Which argument slot of the given call an offset falls into, for signature help
+    ///
+    /// Walks the call's arguments and reports the 0-based positional slot, the flag, or the
+    /// flag whose value the offset is inside. A non-flag argument directly following a flag
+    /// counts as that flag's value and doesn't occupy a positional slot. Returns None when the
+    /// node is not a call or the offset is outside every argument.
+    pub fn call_argument_at(&self, call_node: NodeId, offset: usize) -> Option<ArgPosition> {
+        let AstNode::Call { ref parts } = self.ast_nodes[call_node.0] else {
+            return None;
+        };
+
+        let mut positional = 0;
+        let mut prev_flag: Option<NodeId> = None;
+        for part in &parts[1..] {
+            let span = self.spans[part.0];
+            let is_flag = matches!(
+                self.ast_nodes[part.0],
+                AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup
+            );
+
+            if span.start <= offset && offset <= span.end {
+                return Some(if is_flag {
+                    ArgPosition::Flag(*part)
+                } else if let Some(flag) = prev_flag {
+                    ArgPosition::FlagValue(flag)
+                } else {
+                    ArgPosition::Positional(positional)
+                });
+            }
+
+            if is_flag {
+                prev_flag = Some(*part);
+            } else if prev_flag.take().is_none() {
+                positional += 1;
+            }
+        }
+
+        None
     }
 
-    /// Get the source contents of a node as i64
-    pub fn node_as_i64(&self, node_id: NodeId) -> i64 {
-        self.node_as_str(node_id)
-            .parse::<i64>()
-            .expect("internal error: expected i64")
+    /// Check the internal invariants of the compiler's data structures
+    ///
+    /// Intended for tests and debug builds to catch internal corruption early. Verifies the
+    /// parity of the node-indexed arrays, that every referenced NodeId/BlockId/PipelineId is in
+    /// range, and that the resolution maps reference valid ids.
+    pub fn validate_invariants(&self) -> Result<(), String> {
+        if self.spans.len() != self.ast_nodes.len() {
+            return Err(format!(
+                "spans.len() ({}) != ast_nodes.len() ({})",
+                self.spans.len(),
+                self.ast_nodes.len()
+            ));
+        }
+        if self.node_types.len() > self.ast_nodes.len() {
+            return Err(format!(
+                "node_types.len() ({}) > ast_nodes.len() ({})",
+                self.node_types.len(),
+                self.ast_nodes.len()
+            ));
+        }
+
+        for (idx, node) in self.ast_nodes.iter().enumerate() {
+            for child in node.children() {
+                if child.0 >= self.ast_nodes.len() {
+                    return Err(format!(
+                        "node {idx} ({node:?}) references out-of-range {child:?}"
+                    ));
+                }
+            }
+            match node {
+                AstNode::Block(block_id) if block_id.0 >= self.blocks.len() => {
+                    return Err(format!("node {idx} references out-of-range {block_id:?}"));
+                }
+                AstNode::Pipeline(pipeline_id) if pipeline_id.0 >= self.pipelines.len() => {
+                    return Err(format!("node {idx} references out-of-range {pipeline_id:?}"));
+                }
+                _ => {}
+            }
+        }
+
+        for (block_idx, block) in self.blocks.iter().enumerate() {
+            for node_id in &block.nodes {
+                if node_id.0 >= self.ast_nodes.len() {
+                    return Err(format!(
+                        "block {block_idx} references out-of-range {node_id:?}"
+                    ));
+                }
+            }
+        }
+        for (pipeline_idx, pipeline) in self.pipelines.iter().enumerate() {
+            for node_id in pipeline.get_expressions() {
+                if node_id.0 >= self.ast_nodes.len() {
+                    return Err(format!(
+                        "pipeline {pipeline_idx} references out-of-range {node_id:?}"
+                    ));
+                }
+            }
+        }
+
+        for (node_id, var_id) in &self.var_resolution {
+            if node_id.0 >= self.ast_nodes.len() || var_id.0 >= self.variables.len() {
+                return Err(format!(
+                    "var_resolution maps {node_id:?} to invalid {var_id:?}"
+                ));
+            }
+        }
+        for (node_id, decl_id) in &self.decl_resolution {
+            if node_id.0 >= self.ast_nodes.len() || decl_id.0 >= self.decls.len() {
+                return Err(format!(
+                    "decl_resolution maps {node_id:?} to invalid {decl_id:?}"
+                ));
+            }
+        }
+        for (node_id, type_decl_id) in &self.type_resolution {
+            if node_id.0 >= self.ast_nodes.len() || type_decl_id.0 >= self.type_decls.len() {
+                return Err(format!(
+                    "type_resolution maps {node_id:?} to invalid {type_decl_id:?}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Context-appropriate completion suggestions at the given source offset
+    ///
+    /// Covers four contexts: after `$`, the variables in scope; after `$x.`, the keys of the
+    /// record the variable was initialized with; after `--` inside a call, the command's
+    /// declared flag names; and at command position (start of a statement), both commands and
+    /// variables in scope. File path hints for path-shaped arguments are not offered yet.
+    /// Relies on the name bindings being merged, so it is only useful after the resolver pass.
+    pub fn completions_at(&self, offset: usize) -> Vec<CompletionItem> {
+        let offset = offset.min(self.source.len());
+        let prefix = &self.source[..offset];
+
+        // `$x.` completes the record keys of the variable's initializer
+        if let Some(var_name) = prefix
+            .strip_suffix(b".")
+            .and_then(trailing_variable_name)
+        {
+            return self
+                .record_field_completions(&var_name, offset)
+                .unwrap_or_default();
+        }
+
+        // `$` completes the variables in scope
+        if prefix.ends_with(b"$") {
+            return self.scope_completions(offset, false);
+        }
+
+        // `--` inside a call completes the command's declared flag names
+        if prefix.ends_with(b"--") {
+            if let Some(items) = self.flag_completions(offset) {
+                return items;
+            }
+        }
+
+        if at_command_position(prefix) {
+            return self.scope_completions(offset, true);
+        }
+
+        vec![]
+    }
+
+    /// Declared flag names of the command whose call surrounds the offset
+    fn flag_completions(&self, offset: usize) -> Option<Vec<CompletionItem>> {
+        // innermost call containing the offset (a call's span starts after its head name, so
+        // the flag position is always inside it)
+        let (_, call_id, parts) = self
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, node)| match node {
+                AstNode::Call { parts } => {
+                    let span = self.get_span(NodeId(idx));
+                    (span.start <= offset && offset <= span.end)
+                        .then_some((span.end - span.start, NodeId(idx), parts))
+                }
+                _ => None,
+            })
+            .min_by_key(|(width, _, _)| *width)?;
+
+        let flags = match self.decl_resolution.get(&call_id) {
+            Some(decl_id) => self.decl_signature(*decl_id)?.flags.clone(),
+            // calls without a declaration may still name a builtin
+            None => {
+                let name = self.get_span_contents(*parts.first()?).trim_ascii();
+                self.builtin_signature(name)?.flags
+            }
+        };
+
+        Some(
+            flags
+                .into_iter()
+                .map(|flag| CompletionItem {
+                    label: flag,
+                    kind: CompletionKind::Flag,
+                    detail: None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Variables (and optionally commands) visible in the scope frames containing the offset
+    fn scope_completions(&self, offset: usize, include_commands: bool) -> Vec<CompletionItem> {
+        let mut items = vec![];
+        for frame in self.frames_at(offset) {
+            for name in frame.variables.keys() {
+                items.push(CompletionItem {
+                    label: String::from_utf8_lossy(name).to_string(),
+                    kind: CompletionKind::Variable,
+                    detail: None,
+                });
+            }
+            if include_commands {
+                for name in frame.decls.keys() {
+                    items.push(CompletionItem {
+                        label: String::from_utf8_lossy(name).to_string(),
+                        kind: CompletionKind::Command,
+                        detail: None,
+                    });
+                }
+            }
+        }
+        items.sort_by(|a, b| a.label.cmp(&b.label));
+        items.dedup();
+        items
+    }
+
+    fn record_field_completions(
+        &self,
+        var_name: &[u8],
+        offset: usize,
+    ) -> Option<Vec<CompletionItem>> {
+        // innermost definition of the variable visible at the offset
+        let name_node = self
+            .frames_at(offset)
+            .filter_map(|frame| frame.variables.get(var_name))
+            .last()
+            .copied()?;
+
+        let initializer = self.ast_nodes.iter().find_map(|node| match node {
+            AstNode::Let {
+                variable_name,
+                initializer,
+                ..
+            } if *variable_name == name_node => Some(*initializer),
+            _ => None,
+        })?;
+
+        let AstNode::Record { pairs } = &self.ast_nodes[initializer.0] else {
+            return None;
+        };
+
+        Some(
+            pairs
+                .iter()
+                .map(|(key, value)| CompletionItem {
+                    label: String::from_utf8_lossy(self.get_span_contents(*key)).to_string(),
+                    kind: CompletionKind::Field,
+                    detail: Some(
+                        String::from_utf8_lossy(self.get_span_contents(*value)).to_string(),
+                    ),
+                })
+                .collect(),
+        )
+    }
+
+    /// The type of the pipeline input flowing into the stage containing `offset`
+    ///
+    /// Intended for hover on `$in`: the result is the output type of the previous pipeline
+    /// stage, as computed during typechecking. At the first stage of a pipeline — or outside
+    /// any pipeline, e.g. in a standalone block — there is no input yet, reported as the
+    /// none type. Returns None if the offset is beyond the parsed source. When pipelines
+    /// nest (e.g. in a closure), the innermost one wins.
+    pub fn pipeline_input_type_at(&self, offset: usize) -> Option<TypeId> {
+        let mut best: Option<(usize, TypeId)> = None;
+
+        for (idx, node) in self.ast_nodes.iter().enumerate() {
+            let AstNode::Pipeline(pipeline_id) = node else {
+                continue;
+            };
+            let span = self.get_span(NodeId(idx));
+            if !(span.start <= offset && offset <= span.end) {
+                continue;
+            }
+
+            // a call's span does not cover its head name, so attribute everything up to a
+            // stage's end (from the previous pipe onwards) to that stage
+            let expressions = self.pipelines[pipeline_id.0].get_expressions();
+            let Some(stage) = expressions
+                .iter()
+                .position(|expr| offset <= self.get_span(*expr).end)
+            else {
+                continue;
+            };
+
+            let input = if stage == 0 {
+                NONE_TYPE
+            } else {
+                self.node_type_of(expressions[stage - 1])
+            };
+
+            let width = span.end - span.start;
+            if best.is_none_or(|(best_width, _)| width < best_width) {
+                best = Some((width, input));
+            }
+        }
+
+        match best {
+            Some((_, input)) => Some(input),
+            // un-piped statements have no pipeline node; nothing flows into them
+            None if offset < self.source.len() => Some(NONE_TYPE),
+            None => None,
+        }
+    }
+
+    /// Render a type as the typechecker would, using the tables retained by
+    /// [`Compiler::merge_types`]
+    ///
+    /// Before typechecking, every type renders as "unknown".
+    pub fn type_to_string(&self, type_id: TypeId) -> String {
+        if self.types.is_empty() {
+            return "unknown".to_string();
+        }
+        render_type(
+            type_id,
+            &self.types,
+            &self.record_types,
+            &self.oneof_types,
+            &self.allof_types,
+            &self.type_vars,
+            self,
+        )
+    }
+
+    /// Summarize the inferred types of the program's significant nodes
+    ///
+    /// Reports let bindings, command definitions and pipeline stages with their rendered
+    /// types, in source order — a whole-file "show me what was inferred" report. A let
+    /// binding's entry spans the variable name; a definition's entry spans its name and
+    /// renders the in/out signature (e.g. "any -> int"). Entries whose type carries no
+    /// information (unknown or forbidden) are skipped; use
+    /// [`Compiler::types_summary_filtered`] to keep them.
+    pub fn types_summary(&self) -> Vec<(Span, String)> {
+        self.types_summary_filtered(true)
+    }
+
+    /// Like [`Compiler::types_summary`], with control over whether trivial entries are skipped
+    pub fn types_summary_filtered(&self, skip_trivial: bool) -> Vec<(Span, String)> {
+        let mut summary: Vec<(Span, String)> = vec![];
+
+        for node in &self.ast_nodes {
+            match node {
+                AstNode::Let { variable_name, .. } => {
+                    summary.extend(self.summary_entry(*variable_name, skip_trivial));
+                }
+                AstNode::Def {
+                    name,
+                    in_out_types,
+                    block,
+                    ..
+                } => {
+                    let signature = match in_out_types {
+                        Some(in_out_types) => {
+                            let AstNode::InOutTypes(pairs) = &self.ast_nodes[in_out_types.0]
+                            else {
+                                continue;
+                            };
+                            pairs
+                                .iter()
+                                .filter_map(|pair| {
+                                    let AstNode::InOutType(in_ty, out_ty) =
+                                        self.ast_nodes[pair.0]
+                                    else {
+                                        return None;
+                                    };
+                                    Some(format!(
+                                        "{} -> {}",
+                                        self.type_to_string(self.node_type_of(in_ty)),
+                                        self.type_to_string(self.node_type_of(out_ty))
+                                    ))
+                                })
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        }
+                        // without annotations a command accepts anything and returns
+                        // whatever its body produces
+                        None => format!(
+                            "any -> {}",
+                            self.type_to_string(self.node_type_of(*block))
+                        ),
+                    };
+                    summary.push((self.get_span(*name), signature));
+                }
+                AstNode::Pipeline(pipeline_id) => {
+                    for expr in self.pipelines[pipeline_id.0].get_expressions() {
+                        summary.extend(self.summary_entry(*expr, skip_trivial));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        summary.sort_by_key(|(span, _)| span.start);
+        summary
+    }
+
+    /// A single [`Compiler::types_summary`] entry, unless its type is trivial and skipped
+    fn summary_entry(&self, node_id: NodeId, skip_trivial: bool) -> Option<(Span, String)> {
+        let type_id = self.node_type_of(node_id);
+        if skip_trivial && (type_id == UNKNOWN_TYPE || type_id == FORBIDDEN_TYPE) {
+            return None;
+        }
+        Some((self.get_span(node_id), self.type_to_string(type_id)))
+    }
+
+    /// The checked type of a node, or unknown if the node was never typechecked
+    fn node_type_of(&self, node_id: NodeId) -> TypeId {
+        self.node_types
+            .get(node_id.0)
+            .copied()
+            .unwrap_or(UNKNOWN_TYPE)
+    }
+
+    /// A single-line preview of a node's source text, for outlines and tooltips
+    ///
+    /// Line breaks collapse to spaces, and text longer than `max_len` characters is cut off
+    /// with a trailing ellipsis. Invalid UTF-8 decodes lossily.
+    pub fn span_preview(&self, node_id: NodeId, max_len: usize) -> String {
+        let text = String::from_utf8_lossy(self.get_span_contents(node_id));
+        let mut preview: String = text
+            .chars()
+            .map(|ch| if ch == '\n' || ch == '\r' { ' ' } else { ch })
+            .collect();
+
+        if preview.chars().count() > max_len {
+            preview = preview.chars().take(max_len).collect();
+            preview.push('…');
+        }
+        preview
+    }
+
+    /// The innermost command definition containing `offset`
+    ///
+    /// Intended for breadcrumb-style "current function" context in editors. Top-level code
+    /// belongs to no definition, reported as None. Closures do not declare a command, so
+    /// the nearest enclosing `def` (if any) wins.
+    pub fn enclosing_def(&self, offset: usize) -> Option<DeclId> {
+        let mut best: Option<(usize, DeclId)> = None;
+
+        for (idx, node) in self.ast_nodes.iter().enumerate() {
+            let AstNode::Def { name, .. } = node else {
+                continue;
+            };
+            let span = self.get_span(NodeId(idx));
+            if !(span.start <= offset && offset <= span.end) {
+                continue;
+            }
+            let Some(decl_id) = self.decl_resolution.get(name) else {
+                continue;
+            };
+
+            let width = span.end - span.start;
+            if best.is_none_or(|(best_width, _)| width < best_width) {
+                best = Some((width, *decl_id));
+            }
+        }
+
+        best.map(|(_, decl_id)| decl_id)
+    }
+
+    /// Scope frames whose defining node's span contains the offset
+    fn frames_at(&self, offset: usize) -> impl Iterator<Item = &Frame> {
+        self.scope.iter().filter(move |frame| {
+            let span = self.get_span(frame.node_id);
+            span.start <= offset && offset <= span.end
+        })
+    }
+
+    /// Rendered description of a signature parameter, for help output
+    ///
+    /// Joins the parameter's trailing comment lines into a single line, stripping the leading
+    /// `#` markers. Returns None if the parameter carries no description.
+    pub fn param_description(&self, param_id: NodeId) -> Option<String> {
+        let AstNode::Param {
+            description: Some(description),
+            ..
+        } = self.ast_nodes[param_id.0]
+        else {
+            return None;
+        };
+
+        let text = String::from_utf8_lossy(self.get_span_contents(description)).to_string();
+        let joined = text
+            .lines()
+            .map(|line| line.trim_start().trim_start_matches('#').trim())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Some(joined)
+    }
+
+    /// The parsed signature of a declaration, if one is available
+    ///
+    /// Command definitions get their signature built during resolution; aliases carry none.
+    /// This is the accessor call checking, completion and help build on.
+    pub fn decl_signature(&self, decl_id: DeclId) -> Option<&Signature> {
+        self.decls[decl_id.0].signature()
+    }
+
+    /// Declared signature of a builtin command
+    ///
+    /// Builtins are recognized by name and have no entry in the decl table (see
+    /// [`Compiler::probe_resolution`]), so their signatures come from this table instead.
+    pub fn builtin_signature(&self, name: &[u8]) -> Option<Signature> {
+        let builtin = builtin_command(name)?;
+
+        Some(Signature {
+            name: String::from_utf8_lossy(name).to_string(),
+            positional: builtin.positional.iter().map(|s| s.to_string()).collect(),
+            flags: builtin.flags.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    /// All user-declared commands and aliases, with their signatures and doc comments
+    ///
+    /// Aggregates the declarations into one view for `scope commands`-style introspection,
+    /// documentation generators, and other tooling. The order follows declaration order.
+    pub fn declared_commands(&self) -> Vec<CommandInfo> {
+        self.decl_nodes
+            .iter()
+            .filter_map(|decl_node| {
+                let (name, signature) = match self.ast_nodes[decl_node.0] {
+                    AstNode::Def { name, params, .. } => (
+                        self.span_text_lossy(self.get_span(name)).into_owned(),
+                        self.span_text_lossy(self.get_span(params)).into_owned(),
+                    ),
+                    AstNode::Alias { new_name, .. } => (
+                        self.span_text_lossy(self.get_span(new_name)).into_owned(),
+                        String::new(),
+                    ),
+                    _ => return None,
+                };
+
+                let span = self.get_span(*decl_node);
+                Some(CommandInfo {
+                    name,
+                    signature,
+                    doc_comment: self.doc_comment_above(span.start),
+                    attributes: vec![],
+                    exported: false,
+                    span,
+                })
+            })
+            .collect()
+    }
+
+    /// `#` comment lines directly above the given offset, joined into a single line
+    fn doc_comment_above(&self, offset: usize) -> Option<String> {
+        let mut line_start = self.source[..offset]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |pos| pos + 1);
+
+        let mut lines = vec![];
+        while line_start > 0 {
+            let prev_line_start = self.source[..line_start - 1]
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |pos| pos + 1);
+            let line = String::from_utf8_lossy(&self.source[prev_line_start..line_start - 1]);
+            let trimmed = line.trim();
+            if !trimmed.starts_with('#') {
+                break;
+            }
+            lines.push(trimmed.trim_start_matches('#').trim().to_string());
+            line_start = prev_line_start;
+        }
+
+        if lines.is_empty() {
+            return None;
+        }
+        lines.reverse();
+        Some(
+            lines
+                .into_iter()
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
+    /// Number of places a variable is used, not counting its declaration
+    ///
+    /// A count of zero means the variable is dead code.
+    pub fn var_use_count(&self, var_id: VarId) -> usize {
+        self.var_resolution
+            .values()
+            .filter(|resolved| **resolved == var_id)
+            .count()
+            .saturating_sub(1)
+    }
+
+    /// Number of calls to a declaration, not counting the declaration itself
+    pub fn decl_use_count(&self, decl_id: DeclId) -> usize {
+        self.decl_resolution
+            .values()
+            .filter(|resolved| **resolved == decl_id)
+            .count()
+            .saturating_sub(1)
+    }
+
+    /// Opt-in lint: report `loop` statements whose body contains no `break` or `return`
+    ///
+    /// Such loops never terminate. A conditional break counts as present; a break inside a
+    /// nested loop only exits that inner loop, so it does not count for the outer one.
+    pub fn infinite_loop_diagnostics(&self) -> Vec<SourceError> {
+        let mut diagnostics = vec![];
+
+        for (idx, node) in self.ast_nodes.iter().enumerate() {
+            if let AstNode::Loop { block } = node {
+                if !self.can_terminate_loop(*block) {
+                    diagnostics.push(SourceError {
+                        message: "loop body has no break or return".to_string(),
+                        node_id: NodeId(idx),
+                        severity: Severity::Info,
+                        code: Some("infinite_loop"),
+                    });
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Opt-in lint: report parentheses that change nothing, e.g. `(1)` or `(($x))`
+    ///
+    /// Parens wrapping a single atom can always be removed; the diagnostic's span covers the
+    /// whole parenthesized expression so a quick-fix can replace it with the inner text. Parens
+    /// around binary operations may be needed for precedence and parens around calls make them
+    /// subexpressions (e.g. `(ls)`), so neither is flagged.
+    pub fn redundant_paren_diagnostics(&self) -> Vec<SourceError> {
+        let mut diagnostics = vec![];
+
+        for (idx, node) in self.ast_nodes.iter().enumerate() {
+            if let AstNode::Paren(inner) = node {
+                let redundant = matches!(
+                    self.ast_nodes[inner.0],
+                    AstNode::Int
+                        | AstNode::Float
+                        | AstNode::String
+                        | AstNode::True
+                        | AstNode::False
+                        | AstNode::Null
+                        | AstNode::Variable
+                        | AstNode::CustomLiteral { .. }
+                        | AstNode::Paren(_)
+                );
+                if redundant {
+                    diagnostics.push(SourceError {
+                        message: "redundant parentheses".to_string(),
+                        node_id: NodeId(idx),
+                        severity: Severity::Info,
+                        code: Some("redundant_paren"),
+                    });
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Opt-in lint: report `def` parameters that shadow a variable in scope at the definition
+    ///
+    /// Inside the body the parameter wins, so readers can mistake it for the outer variable.
+    /// Shadowing an ordinary variable is an Info; shadowing a built-in like `$env` or `$nu`
+    /// is a Warning, since the body silently loses access to the built-in. Only variables
+    /// declared before the `def` count as shadowed.
+    pub fn param_shadow_diagnostics(&self) -> Vec<SourceError> {
+        let mut diagnostics = vec![];
+
+        for (idx, node) in self.ast_nodes.iter().enumerate() {
+            let AstNode::Def { params, .. } = node else {
+                continue;
+            };
+            let def_start = self.get_span(NodeId(idx)).start;
+            let AstNode::Params(param_ids) = &self.ast_nodes[params.0] else {
+                continue;
+            };
+
+            for param_id in param_ids {
+                let AstNode::Param { name, .. } = self.ast_nodes[param_id.0] else {
+                    continue;
+                };
+                let param_name = match self.get_span_contents(name) {
+                    [b'$', rest @ ..] => rest,
+                    contents => contents,
+                };
+
+                if matches!(param_name, b"env" | b"nu" | b"in") {
+                    diagnostics.push(SourceError {
+                        message: format!(
+                            "parameter `{}` shadows the built-in variable `${0}`",
+                            String::from_utf8_lossy(param_name)
+                        ),
+                        node_id: name,
+                        severity: Severity::Warning,
+                        code: Some("param_shadow"),
+                    });
+                    continue;
+                }
+
+                let shadowed = self.frames_at(def_start).any(|frame| {
+                    frame
+                        .variables
+                        .get(param_name)
+                        .is_some_and(|decl| self.get_span(*decl).start < def_start)
+                });
+                if shadowed {
+                    diagnostics.push(SourceError {
+                        message: format!(
+                            "parameter `{}` shadows a variable of the same name",
+                            String::from_utf8_lossy(param_name)
+                        ),
+                        node_id: name,
+                        severity: Severity::Info,
+                        code: Some("param_shadow"),
+                    });
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Whether evaluating this node can terminate the innermost enclosing `loop`
+    fn can_terminate_loop(&self, node_id: NodeId) -> bool {
+        match &self.ast_nodes[node_id.0] {
+            AstNode::Break | AstNode::Return(_) => true,
+            // a break inside a nested looping construct only exits that construct, but a
+            // return still exits the enclosing loop
+            AstNode::Loop { block }
+            | AstNode::While { block, .. }
+            | AstNode::For { block, .. } => self.has_return(*block),
+            AstNode::Block(block_id) => self.blocks[block_id.0]
+                .nodes
+                .iter()
+                .any(|inner| self.can_terminate_loop(*inner)),
+            node => node
+                .children()
+                .iter()
+                .any(|child| self.can_terminate_loop(*child)),
+        }
+    }
+
+    /// Whether this node's subtree contains a `return`
+    fn has_return(&self, node_id: NodeId) -> bool {
+        match &self.ast_nodes[node_id.0] {
+            AstNode::Return(_) => true,
+            AstNode::Block(block_id) => self.blocks[block_id.0]
+                .nodes
+                .iter()
+                .any(|inner| self.has_return(*inner)),
+            node => node.children().iter().any(|child| self.has_return(*child)),
+        }
+    }
+
+    /// Report what a node resolved to, without mutating any state
+    ///
+    /// Intended for tooling and tests that want to inspect name binding results after
+    /// resolution: pass a variable node or a call node and get back the resolution category
+    /// (variable, user-defined command, builtin, or unresolved) along with the scope frame the
+    /// name was found in. Builtins don't have a user-visible declaration, so no scope is
+    /// reported for them.
+    pub fn probe_resolution(&self, node_id: NodeId) -> ResolutionInfo {
+        if let Some(var_id) = self.var_resolution.get(&node_id) {
+            let scope = self
+                .scope
+                .iter()
+                .position(|frame| {
+                    frame
+                        .variables
+                        .values()
+                        .any(|name_node| self.var_resolution.get(name_node) == Some(var_id))
+                })
+                .map(ScopeId);
+            return ResolutionInfo {
+                resolution: Resolution::Variable(*var_id),
+                scope,
+            };
+        }
+
+        if let Some(decl_id) = self.decl_resolution.get(&node_id) {
+            let scope = self
+                .scope
+                .iter()
+                .position(|frame| {
+                    frame
+                        .decls
+                        .values()
+                        .any(|name_node| self.decl_resolution.get(name_node) == Some(decl_id))
+                })
+                .map(ScopeId);
+            return ResolutionInfo {
+                resolution: Resolution::Decl(*decl_id),
+                scope,
+            };
+        }
+
+        // calls to builtin commands don't have a declaration to resolve to (trimmed because a
+        // call name's span can include trailing whitespace when it ends its line)
+        if let AstNode::Call { ref parts } = self.ast_nodes[node_id.0] {
+            let name = self.get_span_contents(parts[0]).trim_ascii();
+            if builtin_command(name).is_some() {
+                return ResolutionInfo {
+                    resolution: Resolution::Builtin,
+                    scope: None,
+                };
+            }
+        }
+
+        ResolutionInfo {
+            resolution: Resolution::Unresolved,
+            scope: None,
+        }
+    }
+
+    /// Hierarchical outline of the symbols in the parsed source, for LSP document symbols
+    ///
+    /// Contains command definitions, aliases, modules and variable declarations. Symbols
+    /// defined inside a command's body or a module are nested under it.
+    pub fn document_symbols(&self) -> Vec<DocumentSymbol> {
+        let Some(AstNode::Block(block_id)) = self.ast_nodes.last() else {
+            return vec![];
+        };
+        self.block_symbols(*block_id)
+    }
+
+    /// Linear pre-order view of the AST, for structural (e.g. Myers-style) diffing
+    ///
+    /// Each node becomes one [`FlatNode`] carrying its depth, variant name, and the source text
+    /// for leaf nodes whose literal matters (numbers, strings, names, variables, flags). Spans
+    /// and node ids are deliberately left out, so two scripts that differ only in formatting
+    /// flatten to identical sequences.
+    pub fn flatten(&self) -> Vec<FlatNode> {
+        let mut result = vec![];
+        if self.ast_nodes.is_empty() {
+            return result;
+        }
+
+        let mut stack = vec![(NodeId(self.ast_nodes.len() - 1), 0)];
+        while let Some((node_id, depth)) = stack.pop() {
+            let node = &self.ast_nodes[node_id.0];
+
+            let text = match node {
+                AstNode::Int
+                | AstNode::Float
+                | AstNode::String
+                | AstNode::Name
+                | AstNode::Variable
+                | AstNode::CustomLiteral { .. }
+                | AstNode::FlagLong
+                | AstNode::FlagShort
+                | AstNode::FlagShortGroup => {
+                    Some(String::from_utf8_lossy(self.get_span_contents(node_id)).into_owned())
+                }
+                _ => None,
+            };
+
+            let debug = format!("{node:?}");
+            let kind = debug
+                .split([' ', '('])
+                .next()
+                .expect("split always yields at least one piece")
+                .to_string();
+
+            result.push(FlatNode { depth, kind, text });
+
+            // blocks and pipelines reference their contents via BlockId/PipelineId rather than
+            // through children()
+            let children = match node {
+                AstNode::Block(block_id) => self.blocks[block_id.0].nodes.clone(),
+                AstNode::Pipeline(pipeline_id) => self.pipelines[pipeline_id.0].nodes.clone(),
+                node => node.children(),
+            };
+            for child in children.into_iter().rev() {
+                stack.push((child, depth + 1));
+            }
+        }
+
+        result
+    }
+
+    fn block_symbols(&self, block_id: BlockId) -> Vec<DocumentSymbol> {
+        let mut symbols = vec![];
+        for node_id in &self.blocks[block_id.0].nodes {
+            match self.ast_nodes[node_id.0] {
+                AstNode::Def { name, block, .. } => {
+                    let children = if let AstNode::Block(inner_id) = self.ast_nodes[block.0] {
+                        self.block_symbols(inner_id)
+                    } else {
+                        vec![]
+                    };
+                    symbols.push(self.symbol(SymbolKind::Function, name, *node_id, children));
+                }
+                AstNode::Module { name, block } => {
+                    let children = if let AstNode::Block(inner_id) = self.ast_nodes[block.0] {
+                        self.block_symbols(inner_id)
+                    } else {
+                        vec![]
+                    };
+                    symbols.push(self.symbol(SymbolKind::Module, name, *node_id, children));
+                }
+                AstNode::Alias { new_name, .. } => {
+                    symbols.push(self.symbol(SymbolKind::Function, new_name, *node_id, vec![]));
+                }
+                AstNode::Let { variable_name, .. } => {
+                    symbols.push(self.symbol(SymbolKind::Variable, variable_name, *node_id, vec![]));
+                }
+                _ => {}
+            }
+        }
+        symbols
+    }
+
+    fn symbol(
+        &self,
+        kind: SymbolKind,
+        name_id: NodeId,
+        node_id: NodeId,
+        children: Vec<DocumentSymbol>,
+    ) -> DocumentSymbol {
+        DocumentSymbol {
+            name: String::from_utf8_lossy(self.get_span_contents(name_id)).to_string(),
+            kind,
+            span: self.get_span(node_id),
+            selection_span: self.get_span(name_id),
+            children,
+        }
+    }
+
+    pub fn get_rollback_point(&self, token_pos: usize) -> RollbackPoint {
+        RollbackPoint {
+            idx_span_start: self.spans.len(),
+            idx_nodes: self.ast_nodes.len(),
+            idx_errors: self.errors.len(),
+            idx_blocks: self.blocks.len(),
+            token_pos,
+        }
+    }
+
+    pub fn apply_compiler_rollback(&mut self, rbp: RollbackPoint) -> usize {
+        self.blocks.truncate(rbp.idx_blocks);
+        self.ast_nodes.truncate(rbp.idx_nodes);
+        self.errors.truncate(rbp.idx_errors);
+        self.spans.truncate(rbp.idx_span_start);
+        self.synthetic_nodes.retain(|node_id| node_id.0 < rbp.idx_nodes);
+
+        rbp.token_pos
+    }
+
+    /// Get span of node
+    pub fn get_span(&self, node_id: NodeId) -> Span {
+        *self
+            .spans
+            .get(node_id.0)
+            .expect("internal error: missing span of node")
+    }
+
+    /// Get the source contents of a span of a node
+    pub fn get_span_contents(&self, node_id: NodeId) -> &[u8] {
+        let span = self.get_span(node_id);
+        self.source
+            .get(span.start..span.end)
+            .expect("internal error: missing source of span")
+    }
+
+    /// Decode the backslash escapes of a bare external argument
+    ///
+    /// For arguments like `a\ b.txt`, this is the text the external command actually
+    /// receives: `\ ` becomes a space, `\t` a tab, `\n` a newline and `\\` a backslash.
+    /// Any other backslash sequence is kept literal (matching Windows-style paths, where
+    /// backslashes separate components).
+    pub fn decode_bareword(&self, node_id: NodeId) -> Vec<u8> {
+        let contents = self.get_span_contents(node_id);
+        let mut decoded = Vec::with_capacity(contents.len());
+        let mut bytes = contents.iter().copied().peekable();
+        while let Some(byte) = bytes.next() {
+            if byte != b'\\' {
+                decoded.push(byte);
+                continue;
+            }
+            match bytes.peek() {
+                Some(b' ') => {
+                    decoded.push(b' ');
+                    bytes.next();
+                }
+                Some(b't') => {
+                    decoded.push(b'\t');
+                    bytes.next();
+                }
+                Some(b'n') => {
+                    decoded.push(b'\n');
+                    bytes.next();
+                }
+                Some(b'\\') => {
+                    decoded.push(b'\\');
+                    bytes.next();
+                }
+                _ => decoded.push(b'\\'),
+            }
+        }
+        decoded
+    }
+
+    /// Get the source contents of a span
+    pub fn get_span_contents_manual(&self, span_start: usize, span_end: usize) -> &[u8] {
+        self.source
+            .get(span_start..span_end)
+            .expect("internal error: missing source of span")
+    }
+
+    /// Get the source text of a span, replacing invalid UTF-8 with replacement characters
+    ///
+    /// Unlike node_as_str(), this never panics on malformed bytes, so it is safe for rendering
+    /// diagnostics over untrusted input.
+    pub fn span_text_lossy(&self, span: Span) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(self.get_span_contents_manual(span.start, span.end))
+    }
+
+    /// Get the source contents of a node
+    pub fn node_as_str(&self, node_id: NodeId) -> &str {
+        std::str::from_utf8(self.get_span_contents(node_id))
+            .expect("internal error: expected utf8 string")
+    }
+
+    /// Get the source contents of a node as i64
+    pub fn node_as_i64(&self, node_id: NodeId) -> i64 {
+        self.node_as_str(node_id)
+            .parse::<i64>()
+            .expect("internal error: expected i64")
+    }
+}
+
+/// Normalize CRLF/CR line endings to LF
+///
+/// Returns the normalized contents along with the positions (relative to the normalized
+/// contents) of newlines that replaced a two-byte CRLF sequence.
+fn normalize_line_endings(contents: &[u8]) -> (Vec<u8>, Vec<usize>) {
+    let mut normalized = Vec::with_capacity(contents.len());
+    let mut crlf_positions = vec![];
+
+    let mut pos = 0;
+    while pos < contents.len() {
+        match contents[pos] {
+            b'\r' if contents.get(pos + 1) == Some(&b'\n') => {
+                crlf_positions.push(normalized.len());
+                normalized.push(b'\n');
+                pos += 2;
+            }
+            b'\r' => {
+                normalized.push(b'\n');
+                pos += 1;
+            }
+            byte => {
+                normalized.push(byte);
+                pos += 1;
+            }
+        }
+    }
+
+    (normalized, crlf_positions)
+}
+
+/// Name of the variable whose `$name` reference ends the given prefix, if any
+fn trailing_variable_name(prefix: &[u8]) -> Option<Vec<u8>> {
+    let end = prefix.len();
+    let mut start = end;
+    while start > 0 && (prefix[start - 1].is_ascii_alphanumeric() || prefix[start - 1] == b'_') {
+        start -= 1;
+    }
+    if start == end || start == 0 || prefix[start - 1] != b'$' {
+        return None;
+    }
+    Some(prefix[start..end].to_vec())
+}
+
+/// Whether the cursor (possibly mid-word) sits at the start of a statement
+fn at_command_position(prefix: &[u8]) -> bool {
+    let mut end = prefix.len();
+    while end > 0 && (prefix[end - 1].is_ascii_alphanumeric() || prefix[end - 1] == b'_') {
+        end -= 1;
+    }
+    matches!(
+        prefix[..end].last(),
+        None | Some(b'\n' | b';' | b'|' | b'{' | b'(')
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{
+        ArgPosition, Compiler, CompletionKind, CustomTypeId, Resolution, SourceMapEntry, Span,
+        SpanMode, SymbolKind,
+    };
+    use crate::errors::{DiagnosticConfig, DiagnosticLevel, Severity, SourceError};
+    use crate::lexer::{lex, Token};
+    use crate::parser::{AstNode, NodeId, Parser};
+    use crate::resolver::{DeclId, Resolver, VarId};
+    use crate::typechecker::{Type, Typechecker, INT_TYPE, NONE_TYPE, UNKNOWN_TYPE};
+
+    /// Lex, parse and resolve the source, returning the compiler with name bindings merged
+    fn prepare(source: &[u8]) -> Compiler {
+        let mut compiler = Compiler::new();
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", source);
+
+        let (tokens, err) = lex(source, span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let mut compiler = parser.parse();
+        assert!(compiler.errors.is_empty());
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+        compiler
+    }
+
+    fn var_names(compiler: &Compiler, vars: &[VarId]) -> Vec<String> {
+        vars.iter()
+            .map(|var_id| {
+                let node_id = compiler
+                    .var_resolution
+                    .iter()
+                    .filter(|(_, resolved)| *resolved == var_id)
+                    .map(|(node_id, _)| *node_id)
+                    .min_by_key(|node_id| node_id.0)
+                    .expect("missing name node of variable");
+                String::from_utf8_lossy(compiler.get_span_contents(node_id)).to_string()
+            })
+            .collect()
+    }
+
+    fn call_named(compiler: &Compiler, name: &[u8]) -> NodeId {
+        compiler
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .find_map(|(idx, node)| match node {
+                AstNode::Call { parts }
+                    if compiler.get_span_contents(parts[0]).trim_ascii() == name =>
+                {
+                    Some(NodeId(idx))
+                }
+                _ => None,
+            })
+            .expect("missing call")
+    }
+
+    #[test]
+    fn use_counts_are_aggregated_from_resolution_maps() {
+        let compiler = prepare(
+            b"def foo [y] { $y }\nlet a = 1\nlet b = 2\nlet c = $a + $a\nfoo 1\nfoo 2\nfoo 3\n",
+        );
+
+        let var_id_of = |name: &[u8]| {
+            *compiler
+                .var_resolution
+                .iter()
+                .find(|(node, _)| compiler.get_span_contents(**node) == name)
+                .map(|(_, var_id)| var_id)
+                .expect("missing variable")
+        };
+
+        assert_eq!(compiler.var_use_count(var_id_of(b"a")), 2);
+        assert_eq!(compiler.var_use_count(var_id_of(b"b")), 0);
+
+        let decl_id = *compiler
+            .decl_resolution
+            .iter()
+            .find(|(node, _)| compiler.get_span_contents(**node) == b"foo")
+            .map(|(_, decl_id)| decl_id)
+            .expect("missing declaration");
+
+        assert_eq!(compiler.decl_use_count(decl_id), 3);
+    }
+
+    #[test]
+    fn into_parts_round_trips_through_from_parts() {
+        let compiler = prepare(b"let x = 1\n$x + 2\n");
+
+        let ast_nodes = compiler.ast_nodes.clone();
+        let spans = compiler.spans.clone();
+        let source = compiler.source.clone();
+        let file_offsets = compiler.file_offsets.clone();
+
+        let parts = compiler.into_parts();
+        let restored = Compiler::from_parts(parts);
+
+        assert_eq!(restored.ast_nodes, ast_nodes);
+        assert_eq!(restored.spans, spans);
+        assert_eq!(restored.source, source);
+        assert_eq!(restored.file_offsets, file_offsets);
+        assert!(restored.errors.is_empty());
+        // name bindings are not part of the serializable state
+        assert!(restored.var_resolution.is_empty());
+    }
+
+    #[test]
+    fn source_hash_is_stable_for_identical_contents() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("a.nu", b"let x = 1\n");
+        compiler.add_file("b.nu", b"let x = 1\n");
+        compiler.add_file("c.nu", b"let x = 2\n");
+
+        assert_eq!(compiler.source_hash("a.nu"), compiler.source_hash("b.nu"));
+        assert_ne!(compiler.source_hash("a.nu"), compiler.source_hash("c.nu"));
+        assert_eq!(compiler.source_hash("missing.nu"), None);
+    }
+
+    #[test]
+    fn error_sink_receives_each_error_in_push_order() {
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let sink_seen = std::rc::Rc::clone(&seen);
+        let mut compiler = Compiler::new().with_error_sink(move |error: &SourceError| {
+            sink_seen.borrow_mut().push(error.message.clone());
+        });
+
+        let source = b"[1,,2]\n[,1]\n";
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", source);
+
+        let (tokens, err) = lex(source, span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let compiler = parser.parse();
+
+        assert!(!compiler.errors.is_empty());
+        let messages: Vec<String> = compiler
+            .errors
+            .iter()
+            .map(|error| error.message.clone())
+            .collect();
+        assert_eq!(*seen.borrow(), messages);
+    }
+
+    #[test]
+    fn source_statements_inline_with_provenance_when_enabled() {
+        let source = b"source tests/each_input_kind.nu\n";
+        let mut compiler = Compiler::new();
+        compiler.set_inline_sourced_files(true);
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", source);
+
+        let (tokens, err) = lex(source, span_offset);
+        assert!(err.is_ok());
+
+        let compiler = Parser::new(compiler, tokens).parse();
+        assert!(compiler.errors.is_empty());
+
+        // the enclosing block holds the source statement plus the inlined statements
+        let Some(AstNode::Block(block_id)) = compiler.ast_nodes.last() else {
+            panic!("expected a block");
+        };
+        let nodes = &compiler.blocks[block_id.0].nodes;
+        assert!(matches!(
+            compiler.ast_nodes[nodes[0].0],
+            AstNode::Source { env: false, .. }
+        ));
+        assert_eq!(nodes.len(), 4);
+
+        // the inlined statements keep pointing at the sourced file
+        let inlined_start = compiler.get_span(nodes[1]).start;
+        let (fname, _, _) = compiler
+            .file_offsets
+            .iter()
+            .find(|(_, start, end)| inlined_start >= *start && inlined_start < *end)
+            .unwrap();
+        assert!(fname.ends_with("tests/each_input_kind.nu"));
+    }
+
+    #[test]
+    fn max_parse_time_stops_parsing_and_reports_a_timeout() {
+        let mut source = Vec::new();
+        for i in 0..1000 {
+            source.extend_from_slice(format!("let x{i} = {i}\n").as_bytes());
+        }
+
+        let mut compiler = Compiler::new().with_max_parse_time(std::time::Duration::ZERO);
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", &source);
+
+        let (tokens, err) = lex(&source, span_offset);
+        assert!(err.is_ok());
+
+        let compiler = Parser::new(compiler, tokens).parse();
+        assert!(compiler
+            .errors
+            .iter()
+            .any(|error| error.message == "parse timed out"));
+        // parsing stopped at the first statement boundary, leaving a partial tree
+        assert!(compiler.ast_nodes.len() < 100);
+    }
+
+    #[test]
+    fn diagnostic_config_overrides_code_severities() {
+        let mut config = DiagnosticConfig::new();
+        config.set("param_shadow", DiagnosticLevel::Off);
+        config.set("infinite_loop", DiagnosticLevel::Report(Severity::Error));
+
+        let mut compiler = Compiler::new().with_diagnostic_config(config);
+
+        // a code set to off is never pushed
+        compiler.push_error(SourceError {
+            message: "parameter `x` shadows a variable of the same name".to_string(),
+            node_id: NodeId(0),
+            severity: Severity::Info,
+            code: Some("param_shadow"),
+        });
+        assert!(compiler.errors.is_empty());
+
+        // a code mapped to a severity is promoted from its default
+        compiler.push_error(SourceError {
+            message: "loop body has no break or return".to_string(),
+            node_id: NodeId(0),
+            severity: Severity::Info,
+            code: Some("infinite_loop"),
+        });
+        assert_eq!(compiler.errors.len(), 1);
+        assert!(matches!(compiler.errors[0].severity, Severity::Error));
+
+        // diagnostics without a code keep their default severity
+        compiler.push_error(SourceError {
+            message: "unrelated".to_string(),
+            node_id: NodeId(0),
+            severity: Severity::Warning,
+            code: None,
+        });
+        assert_eq!(compiler.errors.len(), 2);
+        assert!(matches!(compiler.errors[1].severity, Severity::Warning));
+    }
+
+    #[test]
+    fn add_fragment_shifts_spans_by_the_base_offset() {
+        let source = b"1 + 2";
+        let base_offset = 100;
+
+        let mut compiler = Compiler::new();
+        compiler.add_fragment("<fragment>", source, base_offset);
+
+        let (tokens, err) = lex(source, base_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let compiler = parser.parse();
+        assert!(compiler.errors.is_empty());
+
+        let expr = NodeId(compiler.ast_nodes.len() - 1);
+        let span = compiler.get_span(expr);
+        assert_eq!(span.start, base_offset);
+        assert_eq!(span.end, base_offset + source.len());
+        assert_eq!(compiler.get_span_contents(expr), source);
+    }
+
+    #[test]
+    fn span_preview_collapses_and_truncates() {
+        let source = b"{ a: 1,\nb: 2 }\n";
+        let compiler = prepare(source);
+        let record = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::Record { .. }))
+            .expect("missing record node");
+        let int = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::Int))
+            .expect("missing int node");
+
+        // a short node renders verbatim
+        assert_eq!(compiler.span_preview(NodeId(int), 80), "1");
+        // a multi-line node collapses to a single line
+        assert_eq!(compiler.span_preview(NodeId(record), 80), "{ a: 1, b: 2 }");
+        // a long node is cut off with an ellipsis
+        assert_eq!(compiler.span_preview(NodeId(record), 6), "{ a: 1…");
+    }
+
+    #[test]
+    fn enclosing_def_finds_the_innermost_definition() {
+        let source = b"def outer [] {\n  def inner [] { 42 }\n  inner\n}\n3\n";
+        let compiler = prepare(source);
+
+        let def_name = |offset: usize| {
+            compiler
+                .enclosing_def(offset)
+                .map(|decl_id| compiler.decls[decl_id.0].name().to_string())
+        };
+
+        // inside the nested def's body the innermost definition wins
+        let offset = source.windows(2).position(|window| window == b"42").unwrap();
+        assert_eq!(def_name(offset), Some("inner".to_string()));
+
+        // inside the outer body but past the nested def
+        let offset = source.windows(7).position(|window| window == b"inner\n}").unwrap();
+        assert_eq!(def_name(offset), Some("outer".to_string()));
+
+        // top-level code belongs to no definition
+        let offset = source.windows(2).position(|window| window == b"3\n").unwrap();
+        assert_eq!(def_name(offset), None);
+    }
+
+    #[test]
+    fn into_parts_char_mode_serializes_char_offsets() {
+        let source = "let x = \"héllo\"\n".as_bytes();
+        let compiler = prepare(source);
+        let idx = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::String))
+            .expect("missing string node");
+
+        // byte mode (the default) keeps the spans as the compiler uses them internally
+        let byte_parts = compiler.clone().into_parts();
+        assert_eq!(byte_parts.spans[idx], Span::new(8, 16));
+
+        // in char mode the two-byte `é` counts as a single offset step
+        let char_parts = compiler.into_parts_with(SpanMode::Chars);
+        assert_eq!(char_parts.spans[idx], Span::new(8, 15));
+        assert_eq!(char_parts.file_offsets[0].2, 16);
+    }
+
+    #[test]
+    fn types_summary_reports_inferred_types() {
+        let source = b"let x = 5\ndef f [] { 42 }\n5 | into string\n";
+        let mut compiler = prepare(source);
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        compiler.merge_types(typechecker.to_types());
+
+        let summary: Vec<(String, String)> = compiler
+            .types_summary()
+            .into_iter()
+            .map(|(span, ty)| {
+                let text = compiler.get_span_contents_manual(span.start, span.end);
+                (String::from_utf8_lossy(text).to_string(), ty)
+            })
+            .collect();
+
+        // the let binding's inferred type, spanning the variable name
+        assert!(summary.contains(&("x".to_string(), "int".to_string())));
+        // the def's signature type, spanning the command name
+        assert!(summary.contains(&("f".to_string(), "any -> int".to_string())));
+        // the first pipeline stage
+        assert!(summary.contains(&("5".to_string(), "int".to_string())));
+    }
+
+    #[test]
+    fn pipeline_input_type_at_reports_the_prior_stage_output() {
+        let source = b"5 | into string\n";
+        let mut compiler = prepare(source);
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        compiler.merge_types(typechecker.to_types());
+
+        // mid-pipeline the input is the previous stage's output
+        let offset = source
+            .windows(4)
+            .position(|window| window == b"into")
+            .unwrap();
+        assert_eq!(compiler.pipeline_input_type_at(offset), Some(INT_TYPE));
+
+        // the first stage has no input
+        assert_eq!(compiler.pipeline_input_type_at(0), Some(NONE_TYPE));
+
+        // a standalone block's pipeline starts without input as well
+        let source = b"def f [] { $in }\n";
+        let mut compiler = prepare(source);
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        compiler.merge_types(typechecker.to_types());
+
+        let offset = source
+            .windows(3)
+            .position(|window| window == b"$in")
+            .unwrap();
+        assert_eq!(compiler.pipeline_input_type_at(offset), Some(NONE_TYPE));
+    }
+
+    #[test]
+    fn pipeline_input_type_at_is_unknown_before_typechecking() {
+        let source = b"5 | into string\n";
+        let compiler = prepare(source);
+
+        // before merge_types there are no node types yet; the query must not panic
+        let offset = source
+            .windows(4)
+            .position(|window| window == b"into")
+            .unwrap();
+        assert_eq!(compiler.pipeline_input_type_at(offset), Some(UNKNOWN_TYPE));
+    }
+
+    #[test]
+    fn decode_bareword_resolves_backslash_escapes() {
+        let compiler = prepare(b"^touch a\\ b.txt\n^echo a\\tb\n");
+
+        // `a\ b.txt` parses as a single argument with the space escaped
+        let arg = NodeId(1);
+        assert_eq!(compiler.get_span_contents(arg), b"a\\ b.txt");
+        assert_eq!(compiler.decode_bareword(arg), b"a b.txt");
+
+        // `\t` decodes to a tab
+        let arg = NodeId(4);
+        assert_eq!(compiler.decode_bareword(arg), b"a\tb");
+    }
+
+    #[test]
+    fn rename_file_updates_the_recorded_name() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("scratch.nu", b"1 + 2\n");
+
+        assert!(compiler.rename_file("scratch.nu", "saved.nu"));
+        assert_eq!(compiler.file_offsets[0].0, "saved.nu");
+
+        assert!(!compiler.rename_file("missing.nu", "other.nu"));
+        assert_eq!(compiler.file_offsets[0].0, "saved.nu");
+    }
+
+    #[test]
+    fn unterminated_string_errors_at_eof_with_opening_label() {
+        let mut compiler = Compiler::new();
+        let errors = compiler
+            .parse_expression(b"\"abc\nnever closed")
+            .expect_err("expected the unterminated string to error");
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].message.contains("unterminated"));
+        assert!(matches!(errors[1].severity, Severity::Note));
+        // the note points at the opening quote
+        assert_eq!(compiler.get_span(errors[1].node_id).start, 0);
+    }
+
+    #[test]
+    fn decl_signature_exposes_positionals_and_flags() {
+        let compiler = prepare(b"def greet [name: string --shout] { $name }\nalias g = greet\n");
+
+        let sig = compiler
+            .decl_signature(DeclId(0))
+            .expect("missing def signature");
+        assert_eq!(sig.name, "greet");
+        assert_eq!(sig.positional, ["name"]);
+        assert_eq!(sig.flags, ["shout"]);
+
+        // aliases carry no signature
+        assert!(compiler.decl_signature(DeclId(1)).is_none());
+
+        let builtin = compiler
+            .builtin_signature(b"reduce")
+            .expect("missing builtin signature");
+        assert_eq!(builtin.positional, ["closure"]);
+        assert_eq!(builtin.flags, ["fold"]);
+        assert!(compiler.builtin_signature(b"nope").is_none());
+    }
+
+    #[test]
+    fn declared_commands_aggregate_signatures_and_docs() {
+        let compiler = prepare(
+            b"# Adds one\n# to a number\ndef inc [x: int] { $x + 1 }\ndef hidden [] {}\n",
+        );
+        let commands = compiler.declared_commands();
+
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].name, "inc");
+        assert_eq!(commands[0].signature, "[x: int]");
+        assert_eq!(
+            commands[0].doc_comment.as_deref(),
+            Some("Adds one to a number")
+        );
+        assert!(!commands[0].exported);
+        assert_eq!(commands[1].name, "hidden");
+        assert_eq!(commands[1].doc_comment, None);
+    }
+
+    #[test]
+    fn subtree_sizes_grow_with_the_subtree() {
+        let compiler = prepare(b"let x = [1, 2, 3]\n");
+        let list = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::List(_)))
+            .map(NodeId)
+            .expect("expected a list node");
+        let first_int = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::Int))
+            .map(NodeId)
+            .expect("expected an int node");
+
+        assert_eq!(compiler.subtree_byte_len(first_int), 1);
+        assert!(compiler.subtree_byte_len(list) > compiler.subtree_byte_len(first_int));
+        assert_eq!(compiler.subtree_node_count(first_int), 1);
+        assert_eq!(compiler.subtree_node_count(list), 4);
+    }
+
+    #[test]
+    fn call_argument_at_distinguishes_positionals_and_flags() {
+        let compiler = prepare(b"foo 1 22 --bar 3\n");
+        let call = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::Call { .. }))
+            .map(NodeId)
+            .expect("expected a call node");
+
+        assert_eq!(
+            compiler.call_argument_at(call, 7),
+            Some(ArgPosition::Positional(1))
+        );
+        let Some(ArgPosition::Flag(flag)) = compiler.call_argument_at(call, 10) else {
+            panic!("expected a flag position");
+        };
+        assert_eq!(
+            compiler.call_argument_at(call, 15),
+            Some(ArgPosition::FlagValue(flag))
+        );
+    }
+
+    #[test]
+    fn flatten_ignores_formatting_but_not_semantics() {
+        let flat_a = prepare(b"let x = [1, 2]\n").flatten();
+        let flat_b = prepare(b"let  x =  [ 1,2 ]\n\n").flatten();
+        let flat_c = prepare(b"let x = [1, 3]\n").flatten();
+
+        assert_eq!(flat_a, flat_b);
+        assert_ne!(flat_a, flat_c);
+    }
+
+    #[test]
+    fn for_each_error_resolves_lines_and_columns() {
+        let mut compiler = Compiler::new();
+        let source = b"[1,,2]\n[,1]\n";
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", source);
+
+        let (tokens, err) = lex(source, span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let compiler = parser.parse();
+
+        let mut rendered = vec![];
+        compiler.for_each_error(|error, line_text, pos| {
+            rendered.push((error.message.clone(), line_text.to_string(), pos));
+        });
+
+        assert_eq!(rendered.len(), compiler.errors.len());
+        assert_eq!(rendered[0].1, "[1,,2]");
+        assert_eq!(rendered[0].2, (1, 4));
+        let last = rendered.last().expect("expected at least one error");
+        assert_eq!(last.1, "[,1]");
+        assert_eq!(last.2 .0, 2);
+    }
+
+    #[test]
+    fn registered_literal_suffix_parses_and_types() {
+        let mut compiler = Compiler::new();
+        compiler.register_literal_suffix(b"px", CustomTypeId(7));
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", b"let x = 5px\n");
+
+        let (tokens, err) = lex(b"let x = 5px\n", span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let mut compiler = parser.parse();
+        assert!(compiler.errors.is_empty());
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        let literal = compiler
+            .ast_nodes
+            .iter()
+            .position(|node| matches!(node, AstNode::CustomLiteral { .. }))
+            .expect("missing custom literal node");
+        assert_eq!(
+            compiler.ast_nodes[literal],
+            AstNode::CustomLiteral {
+                type_id: CustomTypeId(7)
+            }
+        );
+
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        assert_eq!(
+            typechecker.type_of(NodeId(literal)),
+            Type::Custom(CustomTypeId(7))
+        );
+    }
+
+    #[test]
+    fn unregistered_literal_suffix_stays_a_bare_word() {
+        let compiler = prepare(b"5deg\n");
+        assert!(!compiler
+            .ast_nodes
+            .iter()
+            .any(|node| matches!(node, AstNode::CustomLiteral { .. })));
+        assert!(matches!(compiler.ast_nodes[0], AstNode::Name));
+    }
+
+    #[test]
+    fn parse_expression_accepts_exactly_one_expression() {
+        let mut compiler = Compiler::new();
+        let node_id = compiler
+            .parse_expression(b"1 + 2 # a sum\n")
+            .expect("expected a clean parse");
+        assert!(matches!(
+            compiler.ast_nodes[node_id.0],
+            AstNode::BinaryOp { .. }
+        ));
+
+        let mut compiler = Compiler::new();
+        let errors = compiler
+            .parse_expression(b"1 + 2 3\n")
+            .expect_err("expected trailing tokens to error");
+        assert!(errors[0].message.contains("unexpected tokens"));
+
+        let mut compiler = Compiler::new();
+        let errors = compiler
+            .parse_expression(b"  \n")
+            .expect_err("expected empty input to error");
+        assert!(errors[0].message.contains("expected an expression"));
+    }
+
+    #[test]
+    fn span_text_lossy_handles_invalid_utf8() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("bad.nu", b"foo \xff\xfe bar");
+        compiler.ast_nodes.push(AstNode::Name);
+        compiler.spans.push(Span::new(4, 6));
+
+        assert_eq!(compiler.span_text_lossy(Span::new(4, 6)), "\u{fffd}\u{fffd}");
+        // display_state renders the malformed name without panicking
+        assert!(compiler.display_state().contains("\u{fffd}"));
+    }
+
+    #[test]
+    fn redundant_paren_diagnostics_flag_wrapped_atoms() {
+        let compiler = prepare(b"(1)\n");
+        let diagnostics = compiler.redundant_paren_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0].severity, Severity::Info));
+
+        // needed for precedence
+        let compiler = prepare(b"(1 + 2) * 3\n");
+        assert!(compiler.redundant_paren_diagnostics().is_empty());
+
+        // needed to run the call as a subexpression
+        let compiler = prepare(b"(ls)\n");
+        assert!(compiler.redundant_paren_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn param_shadow_diagnostics_flag_shadowed_outer_variables() {
+        let compiler = prepare(b"let x = 1\ndef f [x] { $x }\n");
+        let diagnostics = compiler.param_shadow_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("shadows a variable"));
+        assert!(matches!(diagnostics[0].severity, Severity::Info));
+
+        // unique parameter names are silent
+        let compiler = prepare(b"let x = 1\ndef f [y] { $y }\n");
+        assert!(compiler.param_shadow_diagnostics().is_empty());
+
+        // a variable declared after the def is not shadowed by it
+        let compiler = prepare(b"def f [x] { $x }\nlet x = 1\n");
+        assert!(compiler.param_shadow_diagnostics().is_empty());
+
+        // built-ins get a stronger warning
+        let compiler = prepare(b"def f [env] { $env }\n");
+        let diagnostics = compiler.param_shadow_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("built-in"));
+        assert!(matches!(diagnostics[0].severity, Severity::Warning));
+    }
+
+    #[test]
+    fn infinite_loop_diagnostics_flag_loops_without_break() {
+        let compiler = prepare(b"loop { break }\n");
+        assert!(compiler.infinite_loop_diagnostics().is_empty());
+
+        let compiler = prepare(b"let c = true\nloop { if $c { break } }\n");
+        assert!(compiler.infinite_loop_diagnostics().is_empty());
+
+        let compiler = prepare(b"loop {\n  foo 1\n}\n");
+        let diagnostics = compiler.infinite_loop_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("no break or return"));
+        assert!(matches!(diagnostics[0].severity, Severity::Info));
+
+        // a break in a nested loop doesn't terminate the outer one
+        let compiler = prepare(b"loop { loop { break } }\n");
+        let diagnostics = compiler.infinite_loop_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn normalize_line_endings_keeps_original_positions() {
+        let mut compiler = Compiler::new();
+        compiler.set_normalize_line_endings(true);
+        compiler.add_file("crlf.nu", b"let x = 1\r\nlet y = 2\r\n");
+
+        assert_eq!(compiler.source, b"let x = 1\nlet y = 2\n");
+
+        // the normalized source parses cleanly
+        let (tokens, err) = lex(&compiler.source.clone(), 0);
+        assert!(err.is_ok());
+        let parser = Parser::new(compiler, tokens);
+        let compiler = parser.parse();
+        assert!(compiler.errors.is_empty());
+
+        // offsets before the first CRLF are unchanged, later ones shift past the dropped bytes
+        assert_eq!(compiler.original_position(4), Some(("crlf.nu", 4)));
+        assert_eq!(compiler.original_position(14), Some(("crlf.nu", 15)));
+        // the final newline replaced a CRLF; it maps to the start of that sequence
+        assert_eq!(compiler.original_position(19), Some(("crlf.nu", 20)));
+    }
+
+    #[test]
+    fn original_position_is_identity_without_normalization() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("plain.nu", b"let x = 1\n");
+
+        assert_eq!(compiler.original_position(4), Some(("plain.nu", 4)));
+    }
+
+    #[test]
+    fn probe_resolution_reports_categories() {
+        let compiler = prepare(b"def foo [y] { $y }\nlet x = 1\n$x\nfoo 1\neach { 1 }\nbar\n");
+
+        let var_ref = compiler
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .find_map(|(idx, node)| match node {
+                AstNode::Variable if compiler.get_span_contents(NodeId(idx)) == b"$x" => {
+                    Some(NodeId(idx))
+                }
+                _ => None,
+            })
+            .expect("missing variable reference");
+
+        let info = compiler.probe_resolution(var_ref);
+        assert!(matches!(info.resolution, Resolution::Variable(_)));
+        let scope = info.scope.expect("missing variable scope");
+        assert!(compiler.scope[scope.0].variables.contains_key(b"x".as_slice()));
+
+        let info = compiler.probe_resolution(call_named(&compiler, b"foo"));
+        assert!(matches!(info.resolution, Resolution::Decl(_)));
+        let scope = info.scope.expect("missing decl scope");
+        assert!(compiler.scope[scope.0].decls.contains_key(b"foo".as_slice()));
+
+        let info = compiler.probe_resolution(call_named(&compiler, b"each"));
+        assert_eq!(info.resolution, Resolution::Builtin);
+        assert_eq!(info.scope, None);
+
+        let info = compiler.probe_resolution(call_named(&compiler, b"bar"));
+        assert_eq!(info.resolution, Resolution::Unresolved);
+        assert_eq!(info.scope, None);
+    }
+
+    #[test]
+    fn validate_invariants_catches_corruption() {
+        let mut compiler = prepare(b"def f [x] { $x }\nlet y = [1 2 3]\n");
+        assert_eq!(compiler.validate_invariants(), Ok(()));
+
+        // a node referencing a NodeId that does not exist
+        compiler.ast_nodes.push(AstNode::Statement(NodeId(9999)));
+        compiler
+            .spans
+            .push(crate::compiler::Span::new(0, 0));
+        let err = compiler
+            .validate_invariants()
+            .expect_err("corruption not caught");
+        assert!(err.contains("out-of-range"));
+
+        // mismatched array lengths
+        compiler.spans.pop();
+        let err = compiler
+            .validate_invariants()
+            .expect_err("length mismatch not caught");
+        assert!(err.contains("spans.len()"));
+    }
+
+    #[test]
+    fn completions_cover_variables_fields_and_commands() {
+        let source = b"def run [] { 1 }\nlet rec = {alpha: 1}\nlet other = $rec.alpha\nrun\n";
+        let compiler = prepare(source);
+
+        let find = |needle: &[u8]| {
+            source
+                .windows(needle.len())
+                .position(|window| window == needle)
+                .expect("needle not found")
+        };
+
+        // after `$`
+        let items = compiler.completions_at(find(b"$rec.") + 1);
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert!(labels.contains(&"rec"));
+        assert!(items.iter().all(|item| item.kind == CompletionKind::Variable));
+
+        // after `$rec.`
+        let items = compiler.completions_at(find(b"$rec.") + 5);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].label, "alpha");
+        assert_eq!(items[0].kind, CompletionKind::Field);
+        assert_eq!(items[0].detail.as_deref(), Some("1"));
+
+        // at command position
+        let items = compiler.completions_at(find(b"run\n"));
+        assert!(items
+            .iter()
+            .any(|item| item.label == "run" && item.kind == CompletionKind::Command));
+        assert!(items
+            .iter()
+            .any(|item| item.label == "rec" && item.kind == CompletionKind::Variable));
+    }
+
+    #[test]
+    fn completions_offer_declared_flags_after_dashes() {
+        let source = b"def greet [name: string --shout --loud] { $name }\ngreet --shout\n";
+        let compiler = prepare(source);
+
+        // right after the `--` of the call argument
+        let offset = source
+            .windows(8)
+            .position(|window| window == b"greet --")
+            .expect("needle not found")
+            + 8;
+        let items = compiler.completions_at(offset);
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["shout", "loud"]);
+        assert!(items.iter().all(|item| item.kind == CompletionKind::Flag));
+
+        // builtins have no declaration; their flags come from the builtin table
+        let source = b"[1, 2] | reduce --fold 0 {|it, acc| $acc + $it }\n";
+        let compiler = prepare(source);
+
+        let offset = source
+            .windows(9)
+            .position(|window| window == b"reduce --")
+            .expect("needle not found")
+            + 9;
+        let items = compiler.completions_at(offset);
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["fold"]);
+    }
+
+    #[test]
+    fn param_descriptions_are_captured_and_rendered() {
+        let compiler =
+            prepare(b"def f [\n  x: int # the x value\n  # continued\n  y\n] { 1 }\n");
+
+        let params: Vec<NodeId> = compiler
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, AstNode::Param { .. }))
+            .map(|(idx, _)| NodeId(idx))
+            .collect();
+        assert_eq!(params.len(), 2);
+
+        assert_eq!(
+            compiler.param_description(params[0]).as_deref(),
+            Some("the x value continued")
+        );
+        assert_eq!(compiler.param_description(params[1]), None);
+    }
+
+    #[test]
+    fn token_at_offset_prefers_preceding_at_boundary() {
+        let compiler = prepare(b"let  x = 1\n");
+
+        // inside an identifier
+        let spanned = compiler.token_at_offset(1).expect("token inside 'let'");
+        assert_eq!(spanned.item, Token::Bareword);
+        assert_eq!((spanned.span.start, spanned.span.end), (0, 3));
+
+        // at the boundary after 'let', the preceding token wins
+        let spanned = compiler.token_at_offset(3).expect("token at boundary");
+        assert_eq!((spanned.span.start, spanned.span.end), (0, 3));
+
+        // strictly inside the whitespace run there is no token
+        assert!(compiler.token_at_offset(4).is_none());
+    }
+
+    #[test]
+    fn errors_by_file_groups_diagnostics() {
+        let mut compiler = Compiler::new();
+        for (fname, contents) in [("a.nu", b"let x = ]\n"), ("b.nu", b"let y = ]\n")] {
+            let span_offset = compiler.span_offset();
+            compiler.add_file(fname, contents);
+
+            let (tokens, err) = lex(contents, span_offset);
+            assert!(err.is_ok());
+
+            let parser = Parser::new(compiler, tokens);
+            compiler = parser.parse();
+        }
+        // an error that does not belong to any file
+        compiler.errors.push(SourceError {
+            message: "dangling".to_string(),
+            node_id: NodeId(9999),
+            severity: Severity::Error,
+            code: None,
+        });
+
+        let grouped = compiler.errors_by_file();
+        assert_eq!(
+            grouped.keys().collect::<Vec<_>>(),
+            vec!["<unknown>", "a.nu", "b.nu"]
+        );
+        assert!(!grouped["a.nu"].is_empty());
+        assert!(!grouped["b.nu"].is_empty());
+        assert_eq!(grouped["<unknown>"].len(), 1);
+    }
+
+    #[test]
+    fn document_symbols_nest_module_contents() {
+        // `const` is not parsed yet, so a `let` stands in for the top-level binding
+        let compiler = prepare(
+            b"module math {\n  def add [] { 1 }\n  def sub [] { 2 }\n}\nlet pi = 3\n",
+        );
+
+        let symbols = compiler.document_symbols();
+        assert_eq!(symbols.len(), 2);
+
+        assert_eq!(symbols[0].name, "math");
+        assert_eq!(symbols[0].kind, SymbolKind::Module);
+        assert_eq!(symbols[0].children.len(), 2);
+        assert_eq!(symbols[0].children[0].name, "add");
+        assert_eq!(symbols[0].children[0].kind, SymbolKind::Function);
+        assert_eq!(symbols[0].children[1].name, "sub");
+        assert_eq!(symbols[0].children[1].kind, SymbolKind::Function);
+
+        assert_eq!(symbols[1].name, "pi");
+        assert_eq!(symbols[1].kind, SymbolKind::Variable);
+    }
+
+    #[test]
+    fn document_symbols_nest_inner_definitions() {
+        let compiler = prepare(
+            b"def outer [] {\n  def inner [] { 3 }\n  inner\n}\nalias o = outer\nlet answer = 42\n",
+        );
+
+        let symbols = compiler.document_symbols();
+        assert_eq!(symbols.len(), 3);
+
+        assert_eq!(symbols[0].name, "outer");
+        assert_eq!(symbols[0].kind, SymbolKind::Function);
+        assert_eq!(symbols[0].children.len(), 1);
+        assert_eq!(symbols[0].children[0].name, "inner");
+        assert_eq!(symbols[0].children[0].kind, SymbolKind::Function);
+
+        assert_eq!(symbols[1].name, "o");
+        assert_eq!(symbols[1].kind, SymbolKind::Function);
+
+        assert_eq!(symbols[2].name, "answer");
+        assert_eq!(symbols[2].kind, SymbolKind::Variable);
+        let selection = symbols[2].selection_span;
+        assert_eq!(
+            compiler.get_span_contents_manual(selection.start, selection.end),
+            b"answer"
+        );
+    }
+
+    #[test]
+    fn block_locals_excludes_nested_blocks() {
+        let compiler = prepare(b"let x = 1\nlet y = 2\nif true { let z = 3 }\n");
+
+        // the file's top-level block is the root node, created last
+        let root = NodeId(compiler.ast_nodes.len() - 1);
+        let AstNode::Block(top_block) = compiler.ast_nodes[root.0] else {
+            panic!("expected the root node to be a block");
+        };
+
+        let locals = compiler.block_locals(top_block);
+        assert_eq!(var_names(&compiler, &locals), vec!["x", "y"]);
+
+        // the `if` body is a nested block with its own locals
+        let nested_block = compiler
+            .ast_nodes
+            .iter()
+            .find_map(|node| match node {
+                AstNode::Block(block_id) if *block_id != top_block => Some(*block_id),
+                _ => None,
+            })
+            .expect("missing nested block");
+
+        let locals = compiler.block_locals(nested_block);
+        assert_eq!(var_names(&compiler, &locals), vec!["z"]);
+    }
+
+    #[test]
+    fn origin_location_maps_error_in_generated_code() {
+        let mut compiler = Compiler::new();
+        let contents = b"let x = ]\n";
+
+        let span_offset = compiler.span_offset();
+        compiler.add_file_with_source_map(
+            "<generated>",
+            contents,
+            vec![
+                SourceMapEntry::new(0, "notebook.nu", 100),
+                SourceMapEntry::new(8, "notebook.nu", 200),
+            ],
+        );
+
+        let (tokens, err) = lex(contents, span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let compiler = parser.parse();
+
+        assert!(!compiler.errors.is_empty());
+        let error_span = compiler.get_span(compiler.errors[0].node_id);
+        assert_eq!(
+            compiler.origin_location(error_span.start),
+            Some(("notebook.nu", 200))
+        );
+    }
+
+    #[test]
+    fn synthetic_nodes_are_flagged() {
+        let mut compiler = Compiler::new();
+        let contents = b"1 + ";
+
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<interactive>", contents);
+
+        let (tokens, err) = lex(contents, span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let compiler = parser.parse();
+
+        // the parser inserts a placeholder for the missing right-hand side
+        assert!(!compiler.errors.is_empty());
+        let placeholder = compiler.errors[0].node_id;
+        assert!(compiler.is_synthetic(placeholder));
+
+        // user-written nodes are not flagged
+        assert!(!compiler.is_synthetic(crate::parser::NodeId(0)));
+    }
+
+    #[test]
+    fn add_file_respects_max_source_bytes() {
+        let mut compiler = Compiler::new();
+        compiler.set_max_source_bytes(Some(10));
+
+        assert!(compiler.add_file("small.nu", b"let x = 3\n"));
+        assert!(compiler.errors.is_empty());
+
+        assert!(!compiler.add_file("big.nu", b"let y = 42\n"));
+        assert_eq!(compiler.errors.len(), 1);
+        assert!(compiler.errors[0]
+            .message
+            .contains("source exceeds maximum size"));
+        // the contents of the rejected file must not end up in the source
+        assert_eq!(compiler.source.len(), 10);
+    }
+
+    #[test]
+    fn parse_expression_rejects_oversized_source() {
+        let mut compiler = Compiler::new();
+        compiler.set_max_source_bytes(Some(4));
+
+        // the rejected contents are not retained, so parsing them would produce
+        // out-of-bounds spans; the size error is returned instead
+        let errors = compiler
+            .parse_expression(b"1 + 2\n")
+            .expect_err("expected the size limit error");
+        assert!(errors[0].message.contains("source exceeds maximum size"));
+        assert!(compiler.source.is_empty());
+    }
+
+    #[test]
+    fn rejected_files_do_not_attach_a_source_map() {
+        let mut compiler = Compiler::new();
+        compiler.set_max_source_bytes(Some(4));
+
+        // the map must not linger and attach to a later file under the same name
+        assert!(!compiler.add_file_with_source_map(
+            "gen.nu",
+            b"let x = 3\n",
+            vec![SourceMapEntry::new(0, "notebook.nu", 100)],
+        ));
+        compiler.set_max_source_bytes(None);
+        assert!(compiler.add_file("gen.nu", b"let x = 3\n"));
+
+        assert_eq!(compiler.origin_location(4), None);
+    }
+
+    #[test]
+    fn origin_location_ignores_files_without_source_map() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("spam.nu", b"let x = 3\n");
+
+        assert_eq!(compiler.origin_location(4), None);
+    }
+
+    // the module path tests resolve against the crate's own files since cargo runs tests with
+    // the crate root as the current directory
+
+    #[test]
+    fn resolve_module_path_relative_to_importer() {
+        let compiler = Compiler::new();
+
+        let resolved = compiler
+            .resolve_module_path("tests/alias.nu", "match.nu")
+            .unwrap();
+        assert_eq!(resolved, std::path::PathBuf::from("tests/match.nu"));
+    }
+
+    #[test]
+    fn resolve_module_path_falls_back_to_working_dir() {
+        let mut compiler = Compiler::new();
+        compiler.set_working_dir(Some("tests".into()));
+
+        let resolved = compiler
+            .resolve_module_path("src/lib.rs", "match.nu")
+            .unwrap();
+        assert_eq!(resolved, std::path::PathBuf::from("tests/match.nu"));
+    }
+
+    #[test]
+    fn resolve_module_path_lists_attempted_locations() {
+        let mut compiler = Compiler::new();
+        compiler.set_working_dir(Some("tests".into()));
+
+        let err = compiler
+            .resolve_module_path("src/lib.rs", "no_such.nu")
+            .unwrap_err();
+        assert!(err.contains("src/no_such.nu"));
+        assert!(err.contains("tests/no_such.nu"));
     }
 }
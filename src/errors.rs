@@ -1,9 +1,13 @@
+use std::collections::HashMap;
+
 use crate::parser::NodeId;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Severity {
     Error,
+    Warning,
     Note,
+    Info,
 }
 
 #[derive(Debug, Clone)]
@@ -11,4 +15,44 @@ pub struct SourceError {
     pub message: String,
     pub node_id: NodeId,
     pub severity: Severity,
+    /// Stable diagnostic code (e.g. "param_shadow"), if the diagnostic has one
+    ///
+    /// Codes let a [`DiagnosticConfig`] adjust or suppress the diagnostic without
+    /// matching on the message text.
+    pub code: Option<&'static str>,
+}
+
+/// What to do with a diagnostic of a given code
+#[derive(Debug, Clone, Copy)]
+pub enum DiagnosticLevel {
+    /// Drop the diagnostic entirely
+    Off,
+    /// Report the diagnostic with this severity instead of its default
+    Report(Severity),
+}
+
+/// Per-code severity policy for diagnostics
+///
+/// Host applications map a diagnostic code to the severity they want, or turn it off
+/// entirely; codes without an entry keep their default severity. The policy is consulted
+/// by [`Compiler::push_error`](crate::compiler::Compiler::push_error), so it applies to
+/// diagnostics from every compilation stage.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticConfig {
+    overrides: HashMap<&'static str, DiagnosticLevel>,
+}
+
+impl DiagnosticConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, code: &'static str, level: DiagnosticLevel) {
+        self.overrides.insert(code, level);
+    }
+
+    /// The configured level for `code`, if any
+    pub fn level(&self, code: &str) -> Option<DiagnosticLevel> {
+        self.overrides.get(code).copied()
+    }
 }
@@ -0,0 +1,283 @@
+use crate::compiler::Span;
+use crate::lexer::{lex, Token};
+
+/// Where the opening brace of a block goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BraceStyle {
+    /// `def main [] {` -- the brace stays on the line introducing the block
+    SameLine,
+    /// `def main []` followed by `{` on its own line
+    NextLine,
+}
+
+/// Options controlling the formatter's output
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Number of spaces per indentation level; ignored when use_tabs is set
+    pub indent_width: usize,
+    /// Indent with one tab per level instead of spaces
+    pub use_tabs: bool,
+    pub brace_style: BraceStyle,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            use_tabs: false,
+            brace_style: BraceStyle::SameLine,
+        }
+    }
+}
+
+/// Format the source according to the options
+///
+/// The formatter normalizes each line's leading indentation to match the nesting depth of
+/// parens, brackets and braces, and applies the configured brace placement. It only ever
+/// rewrites whitespace between tokens, so formatting is idempotent, and it leaves lines that
+/// continue a multi-line token (e.g. inside a multi-line string) untouched. Source that does
+/// not lex is returned unchanged.
+pub fn format_source(source: &[u8], options: &FormatOptions) -> Vec<u8> {
+    let moved = apply_brace_style(source, options);
+    reindent(&moved, options)
+}
+
+/// Collect the lexed tokens with their spans, ending with Eof
+///
+/// Returns None if the source does not lex; the formatter must not touch such source.
+fn lex_all(source: &[u8]) -> Option<Vec<(Token, Span)>> {
+    let (mut tokens, err) = lex(source, 0);
+    err.ok()?;
+    tokens.set_pos(0);
+
+    let mut items = vec![];
+    loop {
+        let (token, span) = tokens.peek();
+        items.push((token, span));
+        if token == Token::Eof {
+            return Some(items);
+        }
+        tokens.advance();
+    }
+}
+
+/// Move block-opening braces according to the brace style
+///
+/// Only braces at a line boundary move: a trailing `{` goes to the next line for NextLine,
+/// and a `{` alone on its line joins the previous line for SameLine. Braces in the middle of
+/// a line (e.g. inline closures and records) stay where they are.
+fn apply_brace_style(source: &[u8], options: &FormatOptions) -> Vec<u8> {
+    let Some(items) = lex_all(source) else {
+        return source.to_vec();
+    };
+
+    let mut out = Vec::with_capacity(source.len());
+    let mut copied = 0;
+    for (idx, (token, span)) in items.iter().enumerate() {
+        if *token != Token::LCurly {
+            continue;
+        }
+        let first_on_line = idx == 0 || items[idx - 1].0 == Token::Newline;
+        let last_on_line = matches!(items[idx + 1].0, Token::Newline | Token::Eof);
+
+        match options.brace_style {
+            BraceStyle::NextLine if !first_on_line && last_on_line => {
+                out.extend_from_slice(&source[copied..items[idx - 1].1.end]);
+                out.push(b'\n');
+                copied = span.start;
+            }
+            // joining after a comment would swallow the brace into the comment
+            BraceStyle::SameLine
+                if first_on_line
+                    && last_on_line
+                    && idx >= 2
+                    && !matches!(items[idx - 2].0, Token::Newline | Token::Comment) =>
+            {
+                out.extend_from_slice(&source[copied..items[idx - 2].1.end]);
+                out.push(b' ');
+                copied = span.start;
+            }
+            _ => {}
+        }
+    }
+    out.extend_from_slice(&source[copied..]);
+    out
+}
+
+/// Replace each line's leading whitespace with the indentation of its nesting depth
+fn reindent(source: &[u8], options: &FormatOptions) -> Vec<u8> {
+    let Some(items) = lex_all(source) else {
+        return source.to_vec();
+    };
+
+    let unit: Vec<u8> = if options.use_tabs {
+        vec![b'\t']
+    } else {
+        vec![b' '; options.indent_width]
+    };
+
+    let mut out = Vec::with_capacity(source.len());
+    let mut depth = 0usize;
+    let mut idx = 0;
+    let mut line_start = 0;
+
+    while line_start < source.len() {
+        let line_end = source[line_start..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(source.len(), |pos| line_start + pos);
+
+        // a line starting inside a multi-line token is part of that token's contents
+        let continuation = idx > 0 && items[idx - 1].1.end > line_start;
+
+        // first token starting on this line, if any
+        let first = items[idx..]
+            .iter()
+            .take_while(|(_, span)| span.start < line_end)
+            .map(|(token, _)| *token)
+            .next();
+
+        if continuation {
+            out.extend_from_slice(&source[line_start..line_end]);
+        } else if let Some(first) = first {
+            let indent = if is_closer(first) {
+                depth.saturating_sub(1)
+            } else {
+                depth
+            };
+            for _ in 0..indent {
+                out.extend_from_slice(&unit);
+            }
+            let content_start = line_start
+                + source[line_start..line_end]
+                    .iter()
+                    .take_while(|b| **b == b' ' || **b == b'\t')
+                    .count();
+            out.extend_from_slice(&source[content_start..line_end]);
+        }
+        // blank lines get no indentation
+
+        if line_end < source.len() {
+            out.push(b'\n');
+        }
+
+        // account for the tokens of this line, including its trailing newline token
+        while idx < items.len() && items[idx].1.start <= line_end {
+            let token = items[idx].0;
+            if token == Token::Eof {
+                break;
+            }
+            if is_opener(token) {
+                depth += 1;
+            } else if is_closer(token) {
+                depth = depth.saturating_sub(1);
+            }
+            idx += 1;
+        }
+
+        line_start = line_end + 1;
+    }
+
+    out
+}
+
+fn is_opener(token: Token) -> bool {
+    matches!(
+        token,
+        Token::LParen | Token::LSquare | Token::LCurly | Token::StrInterpLParen
+    )
+}
+
+fn is_closer(token: Token) -> bool {
+    matches!(
+        token,
+        Token::RParen | Token::RSquare | Token::RCurly | Token::StrInterpRParen
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format_str(source: &str, options: &FormatOptions) -> String {
+        String::from_utf8(format_source(source.as_bytes(), options)).unwrap()
+    }
+
+    const NESTED: &str = "def main [] {\nlet x = 1\nif $x > 0 {\n      print $x\n}\n}\n";
+
+    #[test]
+    fn reindents_with_the_configured_width() {
+        let four = FormatOptions::default();
+        assert_eq!(
+            format_str(NESTED, &four),
+            "def main [] {\n    let x = 1\n    if $x > 0 {\n        print $x\n    }\n}\n"
+        );
+
+        let two = FormatOptions {
+            indent_width: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_str(NESTED, &two),
+            "def main [] {\n  let x = 1\n  if $x > 0 {\n    print $x\n  }\n}\n"
+        );
+
+        let tabs = FormatOptions {
+            use_tabs: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_str(NESTED, &tabs),
+            "def main [] {\n\tlet x = 1\n\tif $x > 0 {\n\t\tprint $x\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for brace_style in [BraceStyle::SameLine, BraceStyle::NextLine] {
+            let options = FormatOptions {
+                indent_width: 2,
+                brace_style,
+                ..Default::default()
+            };
+            let once = format_str(NESTED, &options);
+            assert_eq!(format_str(&once, &options), once);
+        }
+    }
+
+    #[test]
+    fn brace_style_moves_block_braces_both_ways() {
+        let next_line = FormatOptions {
+            brace_style: BraceStyle::NextLine,
+            ..Default::default()
+        };
+        let broken = format_str("def main [] {\nlet x = 1\n}\n", &next_line);
+        assert_eq!(broken, "def main []\n{\n    let x = 1\n}\n");
+
+        // SameLine joins it back; inline closures are not touched by either style
+        let same_line = FormatOptions::default();
+        assert_eq!(
+            format_str(&broken, &same_line),
+            "def main [] {\n    let x = 1\n}\n"
+        );
+        assert_eq!(
+            format_str("[1 2] | each {|x| $x }\n", &next_line),
+            "[1 2] | each {|x| $x }\n"
+        );
+    }
+
+    #[test]
+    fn multi_line_strings_and_invalid_source_are_untouched() {
+        let options = FormatOptions {
+            indent_width: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_str("def main [] {\nlet s = \"one\n  two\"\n}\n", &options),
+            "def main [] {\n  let s = \"one\n  two\"\n}\n"
+        );
+
+        let invalid = "let s = \"never closed\n";
+        assert_eq!(format_str(invalid, &options), invalid);
+    }
+}
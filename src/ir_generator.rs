@@ -118,6 +118,7 @@ impl<'a> IrGenerator<'a> {
                 }
                 last
             }
+            AstNode::Paren(inner) => self.generate_node(*inner),
             AstNode::BinaryOp { lhs, op, rhs } => {
                 let l = self.generate_node(*lhs)?;
                 let r = self.generate_node(*rhs)?;
@@ -165,6 +166,7 @@ impl<'a> IrGenerator<'a> {
             message: message.into(),
             node_id,
             severity: Severity::Error,
+            code: None,
         });
     }
 }
@@ -5,6 +5,7 @@ use logos::{Lexer, Logos};
 pub enum LexError {
     #[default]
     Generic,
+    UnterminatedString,
     UnmatchedStrInterpLParen,
     UnmatchedStrInterpRParen,
     UnmatchedRawStringRSharp,
@@ -249,7 +250,7 @@ fn lex_internal(
                 span_offset + span.start,
                 tokens,
             )?,
-            Ok(token) => tokens.push(token, new_span),
+            Ok(token) => push_token_fixed(tokens, token, new_span, contents, span_offset),
             Err(e) => {
                 return Err(Spanned::new(e, new_span));
             }
@@ -259,6 +260,55 @@ fn lex_internal(
     Ok(())
 }
 
+/// Push a token, fixing up ranges mis-lexed as floats
+///
+/// In `1..5`, the float regex greedily matches `1.` and `.5`, so re-split such an adjacent pair
+/// into Int, DotDot, Int. Similarly, `1..` lexes as `1.` followed by a lone dot and becomes
+/// Int, DotDot.
+fn push_token_fixed(
+    tokens: &mut Tokens,
+    token: Token,
+    span: Span,
+    contents: &[u8],
+    span_offset: usize,
+) {
+    let text = |span: Span| &contents[span.start - span_offset..span.end - span_offset];
+
+    if matches!(token, Token::Float | Token::Dot) {
+        if let (Some(Token::Float), Some(&prev_span)) = (tokens.tokens.last(), tokens.spans.last())
+        {
+            if prev_span.end == span.start
+                && text(prev_span).ends_with(b".")
+                && (token == Token::Dot || text(span).starts_with(b"."))
+            {
+                // rewrite the `1.` into `1` and the two dots into `..`
+                *tokens.tokens.last_mut().expect("checked above") = Token::Int;
+                tokens.spans.last_mut().expect("checked above").end = prev_span.end - 1;
+
+                if token == Token::Dot {
+                    tokens.push(Token::DotDot, Span::new(prev_span.end - 1, span.end));
+                } else {
+                    tokens.push(Token::DotDot, Span::new(prev_span.end - 1, span.start + 1));
+
+                    let rest_span = Span::new(span.start + 1, span.end);
+                    let rest_token = if text(rest_span)
+                        .iter()
+                        .any(|byte| matches!(byte, b'.' | b'e' | b'E'))
+                    {
+                        Token::Float
+                    } else {
+                        Token::Int
+                    };
+                    tokens.push(rest_token, rest_span);
+                }
+                return;
+            }
+        }
+    }
+
+    tokens.push(token, span);
+}
+
 /// Lex the source contents and return allocated Tokens.
 ///
 /// In the case of error, you can look up the last stored token to get a clue what went wrong. The
@@ -280,6 +330,10 @@ pub fn lex(contents: &[u8], span_offset: usize) -> (Tokens, Result<(), Spanned<L
     (tokens, Ok(()))
 }
 
+fn match_unterminated_string(_lexer: &mut Lexer<Token>) -> Result<(), LexError> {
+    Err(LexError::UnterminatedString)
+}
+
 fn match_rawstring(remainder: &[u8], lexer: &mut Lexer<Token>) -> Result<(), LexError> {
     let prefix = lexer.slice();
     let prefix_sharp_length = prefix[1..prefix.len() - 1].len(); // without first `r` and last `'`
@@ -310,13 +364,19 @@ fn match_rawstring(remainder: &[u8], lexer: &mut Lexer<Token>) -> Result<(), Lex
 #[logos(skip r"[ \t]+")]
 #[logos(source = [u8], error = LexError)]
 pub enum Token {
-    #[regex("(0[xob])?[0-9][0-9_]*", priority = 10)]
+    #[regex(
+        "0x[0-9a-fA-F][0-9a-fA-F_]*|0o[0-7][0-7_]*|0b[01][01_]*|[0-9][0-9_]*",
+        priority = 10
+    )]
     Int,
     #[regex(r"([0-9][0-9_]*)*\.([0-9][0-9_]*)*([eE][+-]?[0-9_]+)?")]
     Float,
     #[regex("\n|\r\n|\x0C")]
     Newline,
     #[regex(r#""([^"\\]|\\["\\bnfrt])*""#)]
+    // an opening quote that never closes runs to the end of input; report it as its own error
+    // instead of a generic one (the span still starts at the opening quote)
+    #[regex(r#""([^"\\]|\\["\\bnfrt])*"#, match_unterminated_string)]
     DoubleQuotedString,
     #[regex(r#"'[^']*'"#)]
     SingleQuotedString,
@@ -389,6 +449,9 @@ pub enum Token {
     ForwardSlashEquals,
     #[token("/")]
     ForwardSlash,
+    // kept literal in unquoted arguments (e.g., Windows-style paths)
+    #[token("\\")]
+    Backslash,
     #[token("==")]
     EqualsEquals,
     #[token("=~")]
@@ -417,6 +480,8 @@ pub enum Token {
     Comma,
     #[token("?")]
     QuestionMark,
+    #[token("??")]
+    QuestionQuestionMark,
     #[token("^")]
     Caret,
     #[token("@")]
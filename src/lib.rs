@@ -1,5 +1,6 @@
 pub mod compiler;
 pub mod errors;
+pub mod formatter;
 pub mod ir_generator;
 pub mod lexer;
 pub mod parser;
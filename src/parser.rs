@@ -1,12 +1,16 @@
-use crate::compiler::{Compiler, RollbackPoint, Span};
+use crate::compiler::{Compiler, CustomTypeId, RollbackPoint, Span};
 use crate::errors::{Severity, SourceError};
-use crate::lexer::{Token, Tokens};
+use crate::lexer::{lex, Token, Tokens};
 
 use tracy_client::span;
 
 pub struct Parser {
     pub compiler: Compiler,
     tokens: Tokens,
+    /// Wall-clock deadline derived from the compiler's max_parse_time, if one is set
+    deadline: Option<std::time::Instant>,
+    /// Whether the deadline has already been reported, so it errors only once
+    timed_out: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -75,6 +79,26 @@ pub enum ParamsContext {
     Angles,
 }
 
+/// Which output stream(s) a redirection operator applies to and whether it appends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectMode {
+    Out,
+    OutAppend,
+    Err,
+    ErrAppend,
+    OutErr,
+    OutErrAppend,
+}
+
+/// Which exit status of the left command lets the right command of a sequence run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceMode {
+    /// `&&`: run the right side only if the left side succeeded
+    AndThen,
+    /// `||`: run the right side only if the left side failed
+    OrElse,
+}
+
 #[derive(Debug)]
 pub enum BarewordContext {
     /// Bareword is a string (e.g., in a list)
@@ -101,6 +125,11 @@ impl AssignmentOrExpression {
 pub enum AstNode {
     Int,
     Float,
+    /// A numeric literal with a caller-registered suffix, e.g. `5px` (see
+    /// [`Compiler::register_literal_suffix`])
+    CustomLiteral {
+        type_id: CustomTypeId,
+    },
     String,
     Name,
     Type {
@@ -144,6 +173,12 @@ pub enum AstNode {
     And,
     Xor,
     Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitShiftLeft,
+    BitShiftRight,
+    Coalesce,
 
     // Assignments
     Assignment,
@@ -190,6 +225,8 @@ pub enum AstNode {
     Param {
         name: NodeId,
         ty: Option<NodeId>,
+        /// Trailing `# comment` description of the parameter, if any
+        description: Option<NodeId>,
     },
     InOutTypes(Vec<NodeId>),
     /// Input/output type pair for a command
@@ -202,6 +239,25 @@ pub enum AstNode {
         new_name: NodeId,
         old_name: NodeId,
     },
+    /// An `export-env { ... }` block carrying env mutations that run when a module is imported
+    ExportEnv {
+        block: NodeId,
+    },
+    /// A `source file.nu` / `source-env file.nu` statement including another file
+    Source {
+        path: NodeId,
+        env: bool,
+    },
+    /// A `module name { ... }` definition whose declarations can be imported with `use`
+    Module {
+        name: NodeId,
+        block: NodeId,
+    },
+    /// A `use foo/bar baz` import; the path segments address a (possibly nested) module whose
+    /// declarations are brought into scope
+    Use {
+        path: Vec<NodeId>,
+    },
 
     /// Long flag ('--' + one or more letters)
     FlagLong,
@@ -214,6 +270,10 @@ pub enum AstNode {
     Call {
         parts: Vec<NodeId>,
     },
+    /// An external command call (`^cmd ...`); the first part is the command name
+    ExternalCall {
+        parts: Vec<NodeId>,
+    },
     NamedValue {
         name: NodeId,
         value: NodeId,
@@ -238,6 +298,29 @@ pub enum AstNode {
     MemberAccess {
         target: NodeId,
         field: NodeId,
+        /// Optional access (`$x.field?`) yields nothing instead of an error when the field is
+        /// absent
+        optional: bool,
+    },
+    /// A parenthesized subexpression, spanning the parens themselves
+    Paren(NodeId),
+    /// A record spread into a command's named arguments, e.g., `foo ...$opts`
+    Spread(NodeId),
+    /// Redirection of an expression's output stream(s), e.g., `cmd o> file.txt`
+    Redirection {
+        expr: NodeId,
+        mode: RedirectMode,
+        /// Redirection target; None means the stream is discarded (redirected to `null`)
+        target: Option<NodeId>,
+    },
+    /// Exit-status conditional sequencing of two commands, e.g., `cmd1 && cmd2`
+    ///
+    /// Distinct from the boolean `and`/`or` operators: the condition is the left
+    /// command's exit status, not a `bool` value it produced.
+    CondSequence {
+        lhs: NodeId,
+        mode: SequenceMode,
+        rhs: NodeId,
     },
     Block(BlockId),
     Pipeline(PipelineId),
@@ -250,6 +333,16 @@ pub enum AstNode {
         target: NodeId,
         match_arms: Vec<(NodeId, NodeId)>,
     },
+    /// Match arm pattern with a guard (`pattern if $cond => result`)
+    MatchGuard {
+        pattern: NodeId,
+        guard: NodeId,
+    },
+    Try {
+        try_block: NodeId,
+        /// The catch closure, if present
+        catch: Option<NodeId>,
+    },
     Statement(NodeId),
     Garbage,
 }
@@ -257,11 +350,131 @@ pub enum AstNode {
 pub const ASSIGNMENT_PRECEDENCE: usize = 10;
 
 impl AstNode {
+    /// All child nodes directly referenced by this node
+    ///
+    /// Blocks and pipelines reference their contents indirectly via BlockId/PipelineId, so their
+    /// children are not included here.
+    pub fn children(&self) -> Vec<NodeId> {
+        match self {
+            AstNode::Type { name, args, .. } => {
+                let mut children = vec![*name];
+                children.extend(args.iter().copied());
+                children
+            }
+            AstNode::TypeArgs(args) => args.clone(),
+            AstNode::RecordType { fields, .. } => vec![*fields],
+            AstNode::Let {
+                variable_name,
+                ty,
+                initializer,
+                ..
+            } => {
+                let mut children = vec![*variable_name];
+                children.extend(ty.iter().copied());
+                children.push(*initializer);
+                children
+            }
+            AstNode::While { condition, block } => vec![*condition, *block],
+            AstNode::For {
+                variable,
+                range,
+                block,
+            } => vec![*variable, *range, *block],
+            AstNode::Loop { block } => vec![*block],
+            AstNode::Return(expr) => expr.iter().copied().collect(),
+            AstNode::Def {
+                name,
+                type_params,
+                params,
+                in_out_types,
+                block,
+                ..
+            } => {
+                let mut children = vec![*name];
+                children.extend(type_params.iter().copied());
+                children.push(*params);
+                children.extend(in_out_types.iter().copied());
+                children.push(*block);
+                children
+            }
+            AstNode::Params(params) => params.clone(),
+            AstNode::Param {
+                name,
+                ty,
+                description,
+            } => {
+                let mut children = vec![*name];
+                children.extend(ty.iter().copied());
+                children.extend(description.iter().copied());
+                children
+            }
+            AstNode::InOutTypes(types) => types.clone(),
+            AstNode::InOutType(in_ty, out_ty) => vec![*in_ty, *out_ty],
+            AstNode::Closure { params, block } => {
+                let mut children: Vec<NodeId> = params.iter().copied().collect();
+                children.push(*block);
+                children
+            }
+            AstNode::Alias { new_name, old_name } => vec![*new_name, *old_name],
+            AstNode::ExportEnv { block } => vec![*block],
+            AstNode::Source { path, .. } => vec![*path],
+            AstNode::Module { name, block } => vec![*name, *block],
+            AstNode::Use { path } => path.clone(),
+            AstNode::Call { parts } => parts.clone(),
+            AstNode::ExternalCall { parts } => parts.clone(),
+            AstNode::NamedValue { name, value } => vec![*name, *value],
+            AstNode::BinaryOp { lhs, op, rhs } => vec![*lhs, *op, *rhs],
+            AstNode::Range { lhs, rhs } => vec![*lhs, *rhs],
+            AstNode::List(items) => items.clone(),
+            AstNode::Table { header, rows } => {
+                let mut children = vec![*header];
+                children.extend(rows.iter().copied());
+                children
+            }
+            AstNode::Record { pairs } => pairs
+                .iter()
+                .flat_map(|(key, value)| [*key, *value])
+                .collect(),
+            AstNode::MemberAccess { target, field, .. } => vec![*target, *field],
+            AstNode::Paren(inner) => vec![*inner],
+            AstNode::Spread(inner) => vec![*inner],
+            AstNode::Redirection { expr, target, .. } => {
+                let mut children = vec![*expr];
+                children.extend(target.iter().copied());
+                children
+            }
+            AstNode::CondSequence { lhs, rhs, .. } => vec![*lhs, *rhs],
+            AstNode::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                let mut children = vec![*condition, *then_block];
+                children.extend(else_block.iter().copied());
+                children
+            }
+            AstNode::Match { target, match_arms } => {
+                let mut children = vec![*target];
+                children.extend(match_arms.iter().flat_map(|(lhs, rhs)| [*lhs, *rhs]));
+                children
+            }
+            AstNode::MatchGuard { pattern, guard } => vec![*pattern, *guard],
+            AstNode::Try { try_block, catch } => {
+                let mut children = vec![*try_block];
+                children.extend(catch.iter().copied());
+                children
+            }
+            AstNode::Statement(inner) => vec![*inner],
+            _ => vec![],
+        }
+    }
+
     pub fn precedence(&self) -> usize {
         match self {
             AstNode::Pow => 100,
             AstNode::Multiply | AstNode::Divide | AstNode::FloorDiv | AstNode::Modulo => 95,
             AstNode::Plus | AstNode::Minus => 90,
+            AstNode::BitShiftLeft | AstNode::BitShiftRight => 85,
             AstNode::LessThan
             | AstNode::LessThanOrEqual
             | AstNode::GreaterThan
@@ -272,9 +485,13 @@ impl AstNode {
             | AstNode::NotRegexMatch
             | AstNode::In
             | AstNode::Append => 80,
+            AstNode::BitAnd => 75,
+            AstNode::BitXor => 70,
+            AstNode::BitOr => 65,
             AstNode::And => 50,
             AstNode::Xor => 45,
             AstNode::Or => 40,
+            AstNode::Coalesce => 35,
             AstNode::Assignment
             | AstNode::AddAssignment
             | AstNode::SubtractAssignment
@@ -288,7 +505,39 @@ impl AstNode {
 
 impl Parser {
     pub fn new(compiler: Compiler, tokens: Tokens) -> Self {
-        Self { compiler, tokens }
+        let deadline = compiler
+            .max_parse_time
+            .map(|limit| std::time::Instant::now() + limit);
+        Self {
+            compiler,
+            tokens,
+            deadline,
+            timed_out: false,
+        }
+    }
+
+    /// Cooperative check of the parse deadline, done at statement boundaries
+    ///
+    /// On expiry records a single "parse timed out" error; every enclosing statement loop
+    /// then stops as well, so parsing winds down leaving the partial tree usable.
+    fn deadline_expired(&mut self) -> bool {
+        let Some(deadline) = self.deadline else {
+            return false;
+        };
+        if std::time::Instant::now() < deadline {
+            return false;
+        }
+        if !self.timed_out {
+            self.timed_out = true;
+            self.compiler.push_error(SourceError {
+                message: "parse timed out".to_string(),
+                // there is no node to attach the error to
+                node_id: NodeId(0),
+                severity: Severity::Error,
+                code: None,
+            });
+        }
+        true
     }
 
     fn position(&mut self) -> usize {
@@ -306,6 +555,38 @@ impl Parser {
         self.compiler
     }
 
+    /// Parse the source as exactly one expression (expression mode)
+    ///
+    /// Unlike parse(), statements are not allowed, and any trailing tokens after the expression
+    /// are an error. Trailing newlines and comments are fine. Returns the expression's node.
+    pub fn parse_expression(mut self) -> (Compiler, NodeId) {
+        let _span = span!();
+
+        while self.is_newline() || self.is_comment() {
+            self.tokens.advance();
+        }
+        if self.is_eof() {
+            let node_id = self.error("expected an expression");
+            return (self.compiler, node_id);
+        }
+
+        let node_id = self.expression();
+
+        loop {
+            if self.is_eof() {
+                break;
+            }
+            if self.is_newline() || self.is_comment() {
+                self.tokens.advance();
+                continue;
+            }
+            self.error("unexpected tokens after expression");
+            break;
+        }
+
+        (self.compiler, node_id)
+    }
+
     pub fn expression(&mut self) -> NodeId {
         let _span = span!();
         self.math_expression(false).get_node_id()
@@ -319,7 +600,7 @@ impl Parser {
             if self.is_newline() {
                 self.tokens.advance()
             }
-            expressions.push(self.expression());
+            expressions.push(self.expression_or_redirection());
         }
         self.compiler.pipelines.push(Pipeline::new(expressions));
         let span_end = self.position();
@@ -338,17 +619,20 @@ impl Parser {
         if let AssignmentOrExpression::Assignment(_) = &first {
             return first_id;
         }
+        let first_id = self.maybe_redirection(first_id, span_start);
         // pipeline with one element is an expression actually
-        if !self.is_pipe() {
-            return first_id;
-        }
-        self.pipeline(first_id, span_start)
+        let first_id = if self.is_pipe() {
+            self.pipeline(first_id, span_start)
+        } else {
+            first_id
+        };
+        self.maybe_cond_sequence(first_id, span_start)
     }
 
     pub fn pipeline_or_expression(&mut self) -> NodeId {
         let _span = span!();
         let span_start = self.position();
-        let first_id = self.expression();
+        let first_id = self.expression_or_redirection();
         // pipeline with one element is an expression actually.
         if !self.is_pipe() {
             return first_id;
@@ -356,6 +640,120 @@ impl Parser {
         self.pipeline(first_id, span_start)
     }
 
+    /// Parse an expression followed by optional redirection(s) of its output streams
+    pub fn expression_or_redirection(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        let expr = self.expression();
+        self.maybe_redirection(expr, span_start)
+    }
+
+    fn maybe_redirection(&mut self, expr: NodeId, span_start: usize) -> NodeId {
+        let mut expr = expr;
+        while let Some(mode) = self.redirect_mode() {
+            self.tokens.advance();
+            let (target, target_end) = self.redirection_target();
+            let span_end = target.map_or(target_end, |node_id| self.get_span_end(node_id));
+
+            expr = self.create_node(
+                AstNode::Redirection { expr, mode, target },
+                span_start,
+                span_end,
+            );
+        }
+        expr
+    }
+
+    /// Parse the target of a redirection: a bareword path (kept whole, including
+    /// dots and slashes), the discard keyword `null`, or any simple expression.
+    /// Returns the target node (None when the stream is discarded) and its span end.
+    fn redirection_target(&mut self) -> (Option<NodeId>, usize) {
+        let _span = span!();
+        if self.tokens.peek_token() != Token::Bareword {
+            let node_id = self.simple_expression(BarewordContext::String);
+            return (Some(node_id), self.get_span_end(node_id));
+        }
+
+        let span_start = self.position();
+        let mut span_end = self.tokens.peek_span().end;
+        self.tokens.advance();
+        // consume adjacent tokens so a path like `out.txt` stays a single string
+        loop {
+            if self.tokens.peek_span().start != span_end {
+                break;
+            }
+            match self.tokens.peek_token() {
+                Token::Bareword
+                | Token::Int
+                | Token::Float
+                | Token::Dot
+                | Token::DotDot
+                | Token::Dash
+                | Token::ForwardSlash => {
+                    span_end = self.tokens.peek_span().end;
+                    self.tokens.advance();
+                }
+                _ => break,
+            }
+        }
+
+        if self.compiler.get_span_contents_manual(span_start, span_end) == b"null" {
+            // redirecting to `null` discards the stream rather than writing it anywhere
+            (None, span_end)
+        } else {
+            let node_id = self.create_node(AstNode::String, span_start, span_end);
+            (Some(node_id), span_end)
+        }
+    }
+
+    /// Parse any trailing `&&`/`||` command sequence operators, left-associatively
+    fn maybe_cond_sequence(&mut self, expr: NodeId, span_start: usize) -> NodeId {
+        let mut expr = expr;
+        while let Some(mode) = self.sequence_mode() {
+            self.tokens.advance();
+            // the right side may continue on the next line
+            if self.is_newline() {
+                self.tokens.advance()
+            }
+            let rhs = self.pipeline_or_expression();
+            let span_end = self.get_span_end(rhs);
+            expr = self.create_node(
+                AstNode::CondSequence {
+                    lhs: expr,
+                    mode,
+                    rhs,
+                },
+                span_start,
+                span_end,
+            );
+        }
+        expr
+    }
+
+    fn sequence_mode(&mut self) -> Option<SequenceMode> {
+        match self.tokens.peek_token() {
+            Token::AmpersandAmpersand => Some(SequenceMode::AndThen),
+            Token::PipePipe => Some(SequenceMode::OrElse),
+            _ => None,
+        }
+    }
+
+    fn redirect_mode(&mut self) -> Option<RedirectMode> {
+        match self.tokens.peek_token() {
+            Token::OutGreaterThan => Some(RedirectMode::Out),
+            Token::OutGreaterGreaterThan => Some(RedirectMode::OutAppend),
+            Token::ErrGreaterThan => Some(RedirectMode::Err),
+            Token::ErrGreaterGreaterThan => Some(RedirectMode::ErrAppend),
+            Token::OutErrGreaterThan => Some(RedirectMode::OutErr),
+            Token::OutErrGreaterGreaterThan => Some(RedirectMode::OutErrAppend),
+            _ => None,
+        }
+    }
+
+    pub fn is_redirection(&mut self) -> bool {
+        self.redirect_mode().is_some()
+    }
+
     fn math_expression(&mut self, allow_assignment: bool) -> AssignmentOrExpression {
         let _span = span!();
         let mut expr_stack = Vec::<(NodeId, NodeId)>::new();
@@ -369,6 +767,8 @@ impl Parser {
             return AssignmentOrExpression::Expression(self.if_expression());
         } else if self.is_keyword(b"match") {
             return AssignmentOrExpression::Expression(self.match_expression());
+        } else if self.is_keyword(b"try") {
+            return AssignmentOrExpression::Expression(self.try_expression());
         }
         // TODO
         // } else if self.is_keyword(b"where") {
@@ -378,15 +778,12 @@ impl Parser {
         let mut leftmost = self.simple_expression(BarewordContext::Call);
 
         if self.is_equals() {
-            if !allow_assignment {
-                self.error("assignment found in expression");
-            }
             let op = self.operator();
 
             let rhs = self.pipeline_or_expression();
             let span_end = self.get_span_end(rhs);
 
-            return AssignmentOrExpression::Assignment(self.create_node(
+            let node_id = self.create_node(
                 AstNode::BinaryOp {
                     lhs: leftmost,
                     op,
@@ -394,7 +791,14 @@ impl Parser {
                 },
                 span_start,
                 span_end,
-            ));
+            );
+            if !allow_assignment {
+                // an assignment produces nothing, so its value is almost never what the
+                // user wanted
+                self.warning_on_node("assignment used as a value; it produces nothing", node_id);
+            }
+
+            return AssignmentOrExpression::Assignment(node_id);
         }
 
         while self.has_tokens() {
@@ -414,7 +818,7 @@ impl Parser {
                 let op_prec = self.operator_precedence(op);
 
                 if op_prec == ASSIGNMENT_PRECEDENCE && !allow_assignment {
-                    self.error_on_node("assignment found in expression", op);
+                    self.warning_on_node("assignment used as a value; it produces nothing", op);
                 }
 
                 let rhs = if self.is_simple_expression() {
@@ -468,6 +872,19 @@ impl Parser {
         AssignmentOrExpression::Expression(leftmost)
     }
 
+    /// If the bareword is a number followed by a registered literal suffix, the suffix's type id
+    fn custom_literal(&self, span: Span) -> Option<CustomTypeId> {
+        let contents = self.compiler.get_span_contents_manual(span.start, span.end);
+        if !contents.first().is_some_and(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        let suffix_start = contents
+            .iter()
+            .position(|byte| !byte.is_ascii_digit() && *byte != b'_' && *byte != b'.')?;
+        self.compiler
+            .custom_literal_suffix(&contents[suffix_start..])
+    }
+
     pub fn simple_expression(&mut self, bareword_context: BarewordContext) -> NodeId {
         let _span = span!();
 
@@ -487,9 +904,14 @@ impl Parser {
                 if self.tokens.peek_token() == Token::RParen {
                     self.error("use null instead of ()")
                 } else {
-                    let output = self.expression();
+                    // a parenthesized subexpression can be a full pipeline, e.g., (ls | length),
+                    // including `&&`/`||` command sequences
+                    let inner_start = self.position();
+                    let output = self.pipeline_or_expression();
+                    let output = self.maybe_cond_sequence(output, inner_start);
+                    let close_span = self.tokens.peek_span();
                     self.rparen();
-                    output
+                    self.create_node(AstNode::Paren(output), span.start, close_span.end)
                 }
             }
             Token::LSquare => self.list_or_table(),
@@ -498,18 +920,29 @@ impl Parser {
             Token::DoubleQuotedString => self.advance_node(AstNode::String, span),
             Token::SingleQuotedString => self.advance_node(AstNode::String, span),
             Token::Dollar => self.variable(),
+            Token::Caret => self.external_call(),
             Token::Bareword => match self.compiler.get_span_contents_manual(span.start, span.end) {
                 b"true" => self.advance_node(AstNode::True, span),
                 b"false" => self.advance_node(AstNode::False, span),
                 b"null" => self.advance_node(AstNode::Null, span),
-                _ => match bareword_context {
-                    BarewordContext::String => {
-                        let node_id = self.name();
-                        self.compiler.ast_nodes[node_id.0] = AstNode::String;
-                        node_id
+                _ => {
+                    if let Some(type_id) = self.custom_literal(span) {
+                        self.advance_node(AstNode::CustomLiteral { type_id }, span)
+                    } else {
+                        match bareword_context {
+                            BarewordContext::String => {
+                                if let Some(node_id) = self.bareword_path(false) {
+                                    node_id
+                                } else {
+                                    let node_id = self.name();
+                                    self.compiler.ast_nodes[node_id.0] = AstNode::String;
+                                    node_id
+                                }
+                            }
+                            BarewordContext::Call => self.call(),
+                        }
                     }
-                    BarewordContext::Call => self.call(),
-                },
+                }
             },
             _ => self.error("incomplete expression"),
         };
@@ -550,7 +983,16 @@ impl Parser {
                 } else {
                     name
                 };
-                let span_end = self.get_span_end(field_or_call);
+                let mut span_end = self.get_span_end(field_or_call);
+
+                // `?` immediately following the field makes the access optional
+                let optional = if self.is_question_mark() && self.position() == span_end {
+                    span_end = self.tokens.peek_span().end;
+                    self.tokens.advance();
+                    true
+                } else {
+                    false
+                };
 
                 match self.compiler.get_node_mut(field_or_call) {
                     AstNode::Variable | AstNode::Name => {
@@ -558,6 +1000,7 @@ impl Parser {
                             AstNode::MemberAccess {
                                 target: expr,
                                 field: field_or_call,
+                                optional,
                             },
                             span_start,
                             span_end,
@@ -573,6 +1016,90 @@ impl Parser {
         }
     }
 
+    /// Try to parse a bareword as an unquoted Windows-style path (e.g., C:\Users\me\file.nu)
+    ///
+    /// Backslashes are kept literal, matching how Nushell treats unquoted arguments. Returns None
+    /// (without consuming anything) if the bareword is not part of a backslash-separated path.
+    /// With `escape_spaces` (used for external command arguments, where the shell convention
+    /// applies) a backslash before a space escapes it instead, so the word continues past the
+    /// space; see [`Compiler::decode_bareword`] for recovering the decoded text.
+    fn bareword_path(&mut self, escape_spaces: bool) -> Option<NodeId> {
+        let checkpoint = self.tokens.pos();
+        let span_start = self.position();
+        let mut span_end = self.tokens.peek_span().end;
+        let mut has_backslash = false;
+        self.tokens.advance();
+
+        loop {
+            let (token, span) = self.tokens.peek();
+            if span.start != span_end {
+                break;
+            }
+            match token {
+                Token::Backslash => {
+                    has_backslash = true;
+                    // a backslash escapes a single following space, so a bare external
+                    // argument can contain one (e.g. `^touch a\ b.txt` is one argument)
+                    if escape_spaces && self.compiler.source.get(span.end) == Some(&b' ') {
+                        span_end = span.end + 1;
+                        self.tokens.advance();
+                        continue;
+                    }
+                }
+                Token::Bareword
+                | Token::Int
+                | Token::Float
+                | Token::Dot
+                | Token::DotDot
+                | Token::Dash
+                | Token::Colon
+                | Token::ForwardSlash => {}
+                _ => break,
+            }
+            span_end = span.end;
+            self.tokens.advance();
+        }
+
+        if has_backslash {
+            Some(self.create_node(AstNode::String, span_start, span_end))
+        } else {
+            self.tokens.set_pos(checkpoint);
+            None
+        }
+    }
+
+    /// Collect the trailing comment(s) documenting a signature parameter
+    ///
+    /// The description starts with a comment directly after the parameter and joins comment
+    /// lines immediately following it. Returns a node spanning all collected comments.
+    fn param_description(&mut self) -> Option<NodeId> {
+        let mut span: Option<Span> = None;
+
+        loop {
+            if self.is_comment() {
+                let comment_span = self.tokens.peek_span();
+                span = Some(match span {
+                    None => comment_span,
+                    Some(span) => Span::new(span.start, comment_span.end),
+                });
+                self.tokens.advance();
+            } else if self.is_newline() && span.is_some() {
+                let pos = self.tokens.pos();
+                self.tokens.advance();
+                if self.is_comment() {
+                    // a consecutive comment line continues the description
+                    continue;
+                }
+                self.tokens.set_pos(pos);
+                break;
+            } else {
+                break;
+            }
+        }
+
+        span.map(|span| self.create_node(AstNode::Name, span.start, span.end))
+    }
+
     pub fn advance_node(&mut self, node: AstNode, span: Span) -> NodeId {
         self.tokens.advance();
         self.create_node(node, span.start, span.end)
@@ -618,16 +1145,41 @@ impl Parser {
         let span_start = self.position();
 
         while self.has_tokens() {
-            if self.is_newline() {
+            // stop at the end of the enclosing pipeline element or subexpression
+            if self.is_newline()
+                || self.is_semicolon()
+                || self.is_pipe()
+                || self.is_rparen()
+                || self.is_redirection()
+                || self.sequence_mode().is_some()
+            {
                 break;
             }
 
-            if self.is_name() && is_head {
-                parts.push(self.name());
+            if self.is_name() {
+                // an unquoted Windows-style path is an argument, not part of the command name
+                if let Some(arg_id) = self.bareword_path(false) {
+                    is_head = false;
+                    parts.push(arg_id);
+                    continue;
+                }
+                if is_head {
+                    parts.push(self.name());
+                    continue;
+                }
+            }
+
+            if let Some(flag_id) = self.flag() {
+                is_head = false;
+                parts.push(flag_id);
                 continue;
             }
 
-            // TODO: Add flags
+            if let Some(spread_id) = self.spread() {
+                is_head = false;
+                parts.push(spread_id);
+                continue;
+            }
 
             is_head = false;
             let arg_id = self.simple_expression(BarewordContext::String);
@@ -639,22 +1191,153 @@ impl Parser {
         self.create_node(AstNode::Call { parts }, span_start, span_end)
     }
 
+    /// An external command call, e.g., `^cat file.txt`
+    pub fn external_call(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        // the caret itself
+        self.tokens.advance();
+        let mut parts = vec![self.name()];
+
+        while self.has_tokens() {
+            // stop at the end of the enclosing pipeline element or subexpression
+            if self.is_newline()
+                || self.is_semicolon()
+                || self.is_pipe()
+                || self.is_rparen()
+                || self.is_redirection()
+                || self.sequence_mode().is_some()
+            {
+                break;
+            }
+
+            if let Some(arg_id) = self.bareword_path(true) {
+                parts.push(arg_id);
+                continue;
+            }
+
+            parts.push(self.simple_expression(BarewordContext::String));
+        }
+
+        let span_end = self.position();
+
+        self.create_node(AstNode::ExternalCall { parts }, span_start, span_end)
+    }
+
+    /// Parse a `--long` or `-s` flag argument, if the upcoming tokens form one
+    ///
+    /// The dashes and the name must be adjacent, so a lone `-` or `--` followed by whitespace is
+    /// left for the caller to handle.
+    pub fn flag(&mut self) -> Option<NodeId> {
+        let (token, span) = self.tokens.peek();
+        if token != Token::DashDash && token != Token::Dash {
+            return None;
+        }
+
+        let pos = self.tokens.pos();
+        self.tokens.advance();
+
+        let (name, name_span) = self.tokens.peek();
+        if name == Token::Bareword && name_span.start == span.end {
+            self.tokens.advance();
+            let node = if token == Token::DashDash {
+                AstNode::FlagLong
+            } else if name_span.end - name_span.start == 1 {
+                AstNode::FlagShort
+            } else {
+                AstNode::FlagShortGroup
+            };
+            Some(self.create_node(node, span.start, name_span.end))
+        } else {
+            self.tokens.set_pos(pos);
+            None
+        }
+    }
+
+    /// Parse a `...$expr` argument spreading a record into a command's named arguments, if the
+    /// upcoming tokens form one
+    ///
+    /// The dots and the spread expression must be adjacent; a lone `...` followed by
+    /// whitespace is left for the caller to handle.
+    pub fn spread(&mut self) -> Option<NodeId> {
+        let (token, span) = self.tokens.peek();
+        if token != Token::DotDotDot {
+            return None;
+        }
+
+        let pos = self.tokens.pos();
+        self.tokens.advance();
+
+        if self.tokens.peek_span().start != span.end {
+            self.tokens.set_pos(pos);
+            return None;
+        }
+
+        let inner = self.simple_expression(BarewordContext::String);
+        Some(self.create_node(AstNode::Spread(inner), span.start, self.get_span_end(inner)))
+    }
+
+    /// Parse a `--long` flag where one is required, e.g. as a signature parameter name
+    pub fn long_flag(&mut self) -> NodeId {
+        let (token, span) = self.tokens.peek();
+        if token != Token::DashDash {
+            return self.error("expected: long flag");
+        }
+        self.tokens.advance();
+
+        let (name, name_span) = self.tokens.peek();
+        if name == Token::Bareword && name_span.start == span.end {
+            self.tokens.advance();
+            self.create_node(AstNode::FlagLong, span.start, name_span.end)
+        } else {
+            self.error("incomplete flag name")
+        }
+    }
+
     pub fn list_or_table(&mut self) -> NodeId {
         let _span = span!();
         let span_start = self.position();
         let mut is_table = false;
         let mut items = vec![];
 
+        let open_span = self.tokens.peek_span();
         self.lsquare();
         let mut span_end = self.position();
 
+        // a comma may only follow a completed item, so a single trailing comma
+        // is fine but leading or doubled commas are errors
+        let mut comma_allowed = false;
+        // a statement keyword on a fresh line means the list was never closed
+        let mut at_statement_boundary = false;
+
         loop {
             if self.is_rsquare() {
                 span_end = self.position();
                 self.tokens.advance();
                 break;
-            } else if self.is_comma() || self.is_newline() {
-                // TODO: should we disallow `[,,,]`?
+            } else if self.is_eof()
+                || (at_statement_boundary && self.is_statement_keyword())
+            {
+                // recover at the statement boundary so that whatever follows still parses
+                // at the outer level
+                self.unclosed_delimiter("]", open_span);
+                break;
+            } else if self.is_comma() {
+                at_statement_boundary = false;
+                if comma_allowed {
+                    comma_allowed = false;
+                    self.tokens.advance();
+                } else {
+                    self.error("unexpected comma in list");
+                }
+            } else if self.is_line_continuation() {
+                // the continuation hides the line break, so this is not a row or statement
+                // boundary; commas stay governed by the usual rules
+                self.tokens.advance();
+                self.tokens.advance();
+            } else if self.is_newline() {
+                at_statement_boundary = true;
                 self.tokens.advance();
             } else if self.is_semicolon() {
                 if items.len() != 1 {
@@ -664,10 +1347,14 @@ impl Parser {
                 }
                 self.tokens.advance();
                 is_table = true;
+                comma_allowed = false;
             } else if self.is_simple_expression() {
                 items.push(self.simple_expression(BarewordContext::String));
+                comma_allowed = true;
+                at_statement_boundary = false;
             } else {
                 items.push(self.error("expected list item"));
+                at_statement_boundary = false;
                 if self.is_eof() {
                     // prevent forever looping if there is no token to put the error on
                     break;
@@ -700,6 +1387,7 @@ impl Parser {
         // For the record
         let mut items = vec![];
 
+        let open_span = self.tokens.peek_span();
         self.lcurly();
         self.skip_newlines();
 
@@ -721,6 +1409,11 @@ impl Parser {
                 span_end = self.position();
                 break;
             }
+            if self.is_comma() {
+                // leading or doubled comma; a comma may only follow a completed pair
+                self.error("unexpected comma in record");
+                continue;
+            }
             let key = self.simple_expression(BarewordContext::String);
             self.skip_newlines();
             if first_pass && !self.is_colon() {
@@ -738,6 +1431,7 @@ impl Parser {
             }
             if self.is_eof() {
                 // abort when appropriate
+                self.unclosed_delimiter("}", open_span);
                 break;
             }
         }
@@ -787,21 +1481,80 @@ impl Parser {
             Token::AsteriskEquals => self.advance_node(AstNode::MultiplyAssignment, span),
             Token::ForwardSlashEquals => self.advance_node(AstNode::DivideAssignment, span),
             Token::PlusPlusEquals => self.advance_node(AstNode::AppendAssignment, span),
-            Token::Bareword => match self.compiler.get_span_contents_manual(span.start, span.end) {
-                b"mod" => self.advance_node(AstNode::Modulo, span),
-                b"in" => self.advance_node(AstNode::In, span),
-                b"and" => self.advance_node(AstNode::And, span),
-                b"xor" => self.advance_node(AstNode::Xor, span),
-                b"or" => self.advance_node(AstNode::Or, span),
-                op => self.error(format!(
-                    "Unknown operator: '{}'",
-                    String::from_utf8_lossy(op)
-                )),
-            },
+            Token::QuestionQuestionMark => self.advance_node(AstNode::Coalesce, span),
+            Token::Bareword => {
+                if let Some((op, op_span)) = self.peek_bit_operator() {
+                    // consume 'bit', '-' and the operation name
+                    self.tokens.advance();
+                    self.tokens.advance();
+                    self.tokens.advance();
+                    return self.create_node(op, op_span.start, op_span.end);
+                }
+                match self.compiler.get_span_contents_manual(span.start, span.end) {
+                    b"mod" => self.advance_node(AstNode::Modulo, span),
+                    b"in" => self.advance_node(AstNode::In, span),
+                    b"and" => self.advance_node(AstNode::And, span),
+                    b"xor" => self.advance_node(AstNode::Xor, span),
+                    b"or" => self.advance_node(AstNode::Or, span),
+                    op => self.error(format!(
+                        "Unknown operator: '{}'",
+                        String::from_utf8_lossy(op)
+                    )),
+                }
+            }
             _ => self.error("expected: operator"),
         }
     }
 
+    /// Check whether the upcoming tokens form one of the `bit-` operators
+    ///
+    /// `bit-and` lexes as three adjacent tokens ('bit', '-', 'and'), so this peeks past the
+    /// leading bareword without consuming anything and returns the operator node and its full
+    /// span if the tokens line up.
+    fn peek_bit_operator(&mut self) -> Option<(AstNode, Span)> {
+        let (token, span) = self.tokens.peek();
+        if token != Token::Bareword
+            || self.compiler.get_span_contents_manual(span.start, span.end) != b"bit"
+        {
+            return None;
+        }
+
+        let pos = self.tokens.pos();
+        self.tokens.advance();
+        let mut result = None;
+
+        let (dash, dash_span) = self.tokens.peek();
+        if dash == Token::Dash && dash_span.start == span.end {
+            self.tokens.advance();
+            let (name, name_span) = self.tokens.peek();
+            if name == Token::Bareword && name_span.start == dash_span.end {
+                let op = match self
+                    .compiler
+                    .get_span_contents_manual(name_span.start, name_span.end)
+                {
+                    b"and" => Some(AstNode::BitAnd),
+                    b"or" => Some(AstNode::BitOr),
+                    b"xor" => Some(AstNode::BitXor),
+                    b"shl" => Some(AstNode::BitShiftLeft),
+                    b"shr" => Some(AstNode::BitShiftRight),
+                    _ => None,
+                };
+                result = op.map(|op| {
+                    (
+                        op,
+                        Span {
+                            start: span.start,
+                            end: name_span.end,
+                        },
+                    )
+                });
+            }
+        }
+
+        self.tokens.set_pos(pos);
+        result
+    }
+
     pub fn operator_precedence(&mut self, operator: NodeId) -> usize {
         self.compiler.get_node(operator).precedence()
     }
@@ -844,6 +1597,23 @@ impl Parser {
                 break;
             }
 
+            // a delimiter ends the name even when adjacent, e.g. in `(sys).host`
+            if matches!(
+                next_token,
+                Token::Eof
+                    | Token::Newline
+                    | Token::RParen
+                    | Token::RSquare
+                    | Token::RCurly
+                    | Token::Dot
+                    | Token::DotDot
+                    | Token::Pipe
+                    | Token::Semicolon
+                    | Token::Comma
+            ) {
+                break;
+            }
+
             token = next_token;
             span.end = next_span.end;
         }
@@ -879,6 +1649,21 @@ impl Parser {
             } else if self.is_simple_expression() {
                 let pattern = self.simple_expression(BarewordContext::String);
 
+                // optional guard: `pattern if <condition> => result`
+                let pattern = if self.is_keyword(b"if") {
+                    self.tokens.advance();
+                    let guard = self.expression();
+                    let pattern_span_start = self.compiler.get_span(pattern).start;
+                    let guard_span_end = self.get_span_end(guard);
+                    self.create_node(
+                        AstNode::MatchGuard { pattern, guard },
+                        pattern_span_start,
+                        guard_span_end,
+                    )
+                } else {
+                    pattern
+                };
+
                 if !self.is_thick_arrow() {
                     return self.error("expected thick arrow (=>) between match cases");
                 }
@@ -943,6 +1728,36 @@ impl Parser {
         )
     }
 
+    pub fn try_expression(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        let span_end;
+
+        self.keyword(b"try");
+        self.skip_newlines();
+
+        let try_block = self.block(BlockContext::Curlies);
+        self.skip_newlines();
+
+        let catch = if self.is_keyword(b"catch") {
+            self.tokens.advance();
+            self.skip_newlines();
+
+            let closure = self.record_or_closure();
+            span_end = self.get_span_end(closure);
+            Some(closure)
+        } else {
+            span_end = self.get_span_end(try_block);
+            None
+        };
+
+        self.create_node(
+            AstNode::Try { try_block, catch },
+            span_start,
+            span_end,
+        )
+    }
+
     // directly ripped from `type_params` just changed delimiters
     // FIXME: simplify if appropriate
     pub fn signature_params(&mut self, params_context: ParamsContext) -> NodeId {
@@ -977,12 +1792,25 @@ impl Parser {
                     }
                 }
 
-                if self.is_comma() {
+                if self.is_comma() || self.is_newline() || self.is_comment() {
                     self.tokens.advance();
                     continue;
                 }
 
-                let name = self.name();
+                // a flag parameter, e.g. `--help`, otherwise a positional one
+                let name = if self.tokens.peek_token() == Token::DashDash {
+                    self.long_flag()
+                } else if self.tokens.peek_token() == Token::DotDotDot {
+                    // a rest parameter (`...rest`) absorbing any surplus positional
+                    // arguments; the widened span keeps the `...` marker on the name
+                    let dots_span = self.tokens.peek_span();
+                    self.tokens.advance();
+                    let name = self.name();
+                    self.compiler.spans[name.0].start = dots_span.start;
+                    name
+                } else {
+                    self.name()
+                };
 
                 let ty = if self.is_colon() {
                     // We have a type
@@ -1000,8 +1828,19 @@ impl Parser {
                     name_span.end
                 };
 
-                let param =
-                    self.create_node(AstNode::Param { name, ty }, name_span.start, param_span_end);
+                // a trailing `# comment` (possibly continued on following lines) documents
+                // the parameter
+                let description = self.param_description();
+
+                let param = self.create_node(
+                    AstNode::Param {
+                        name,
+                        ty,
+                        description,
+                    },
+                    name_span.start,
+                    param_span_end,
+                );
 
                 // output.push(self.name());
                 output.push(param);
@@ -1338,13 +2177,20 @@ impl Parser {
         let span_start = self.position();
 
         let mut code_body = vec![];
+        // tracked until the matching '}' is consumed, to report unclosed blocks
+        let mut open_span = None;
         if let BlockContext::Curlies = context {
+            open_span = Some(self.tokens.peek_span());
             self.lcurly();
         }
 
         while self.has_tokens() {
+            if self.deadline_expired() {
+                break;
+            }
             if self.is_rcurly() && context == BlockContext::Curlies {
                 self.rcurly();
+                open_span = None;
                 break;
             } else if self.is_rcurly() && context == BlockContext::Closure {
                 // not responsible for parsing it, yield back to the closure pass
@@ -1372,6 +2218,17 @@ impl Parser {
                 code_body.push(self.break_statement());
             } else if self.is_keyword(b"alias") {
                 code_body.push(self.alias_statement());
+            } else if self.is_export_env() {
+                code_body.push(self.export_env_statement());
+            } else if self.is_keyword(b"source") || self.is_source_env() {
+                let source_id = self.source_statement();
+                code_body.push(source_id);
+                // with eager inlining enabled, splice the sourced statements in place
+                code_body.extend(self.process_source(source_id));
+            } else if self.is_keyword(b"module") {
+                code_body.push(self.module_statement());
+            } else if self.is_keyword(b"use") {
+                code_body.push(self.use_statement());
             } else {
                 let exp_span_start = self.position();
                 let pipeline = self.pipeline_or_expression_or_assignment();
@@ -1391,6 +2248,10 @@ impl Parser {
             }
         }
 
+        if let Some(open_span) = open_span {
+            self.unclosed_delimiter("}", open_span);
+        }
+
         self.compiler.blocks.push(Block::new(code_body));
         let span_end = self.position();
 
@@ -1508,6 +2369,231 @@ impl Parser {
         self.create_node(AstNode::Alias { new_name, old_name }, span_start, span_end)
     }
 
+    /// Check whether the upcoming tokens form the `export-env` keyword
+    ///
+    /// `export-env` lexes as three adjacent tokens ('export', '-', 'env'), so this peeks past
+    /// the leading bareword without consuming anything, same as the `bit-` operators.
+    pub fn is_export_env(&mut self) -> bool {
+        if !self.is_keyword(b"export") {
+            return false;
+        }
+
+        let span = self.tokens.peek_span();
+        let pos = self.tokens.pos();
+        self.tokens.advance();
+        let mut result = false;
+
+        let (dash, dash_span) = self.tokens.peek();
+        if dash == Token::Dash && dash_span.start == span.end {
+            self.tokens.advance();
+            let (name, name_span) = self.tokens.peek();
+            result = name == Token::Bareword
+                && name_span.start == dash_span.end
+                && self
+                    .compiler
+                    .get_span_contents_manual(name_span.start, name_span.end)
+                    == b"env";
+        }
+
+        self.tokens.set_pos(pos);
+        result
+    }
+
+    pub fn is_source_env(&mut self) -> bool {
+        if !self.is_keyword(b"source") {
+            return false;
+        }
+
+        let span = self.tokens.peek_span();
+        let pos = self.tokens.pos();
+        self.tokens.advance();
+        let mut result = false;
+
+        let (dash, dash_span) = self.tokens.peek();
+        if dash == Token::Dash && dash_span.start == span.end {
+            self.tokens.advance();
+            let (name, name_span) = self.tokens.peek();
+            result = name == Token::Bareword
+                && name_span.start == dash_span.end
+                && self
+                    .compiler
+                    .get_span_contents_manual(name_span.start, name_span.end)
+                    == b"env";
+        }
+
+        self.tokens.set_pos(pos);
+        result
+    }
+
+    pub fn source_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        let env = self.is_source_env();
+        if env {
+            // consume 'source', '-' and 'env'
+            self.tokens.advance();
+            self.tokens.advance();
+            self.tokens.advance();
+        } else {
+            self.keyword(b"source");
+        }
+
+        let path = if self.is_name() {
+            // merge the adjacent tokens of an unquoted path (e.g. `lib.nu`) into one string
+            let path_start = self.position();
+            let mut path_end = self.tokens.peek_span().end;
+            self.tokens.advance();
+            loop {
+                let (token, span) = self.tokens.peek();
+                if span.start != path_end {
+                    break;
+                }
+                match token {
+                    Token::Bareword
+                    | Token::Int
+                    | Token::Float
+                    | Token::Dot
+                    | Token::DotDot
+                    | Token::Dash
+                    | Token::Colon
+                    | Token::ForwardSlash
+                    | Token::Backslash => {}
+                    _ => break,
+                }
+                path_end = span.end;
+                self.tokens.advance();
+            }
+            self.create_node(AstNode::String, path_start, path_end)
+        } else {
+            self.simple_expression(BarewordContext::String)
+        };
+        let span_end = self.get_span_end(path);
+        self.create_node(AstNode::Source { path, env }, span_start, span_end)
+    }
+
+    /// Resolve a parsed `source` statement's literal path, and optionally inline the file
+    ///
+    /// A literal path must point at an existing file; failures are reported on the path node.
+    /// With [`Compiler::set_inline_sourced_files`] the sourced file's statements are lexed and
+    /// parsed in place and returned, so the caller can splice them into the enclosing block.
+    /// The file goes through add_file(), so diagnostics inside the spliced statements keep
+    /// pointing at the sourced file.
+    fn process_source(&mut self, source_id: NodeId) -> Vec<NodeId> {
+        let AstNode::Source { path, .. } = self.compiler.ast_nodes[source_id.0] else {
+            panic!("internal error: expected source statement");
+        };
+        // non-literal paths (e.g. interpolations) can only be resolved at run time
+        if !matches!(self.compiler.ast_nodes[path.0], AstNode::String) {
+            return vec![];
+        }
+
+        let text = match self.compiler.get_span_contents(path) {
+            [b'"', inner @ .., b'"'] | [b'\'', inner @ .., b'\''] => inner,
+            other => other,
+        };
+        let text = String::from_utf8_lossy(text).to_string();
+
+        let path_start = self.compiler.get_span(path).start;
+        let importer = self
+            .compiler
+            .file_offsets
+            .iter()
+            .find(|(_, start, end)| path_start >= *start && path_start < *end)
+            .map(|(fname, _, _)| fname.clone())
+            .unwrap_or_default();
+
+        let resolved = match self.compiler.resolve_module_path(&importer, &text) {
+            Ok(resolved) => resolved,
+            Err(message) => {
+                self.error_on_node(message, path);
+                return vec![];
+            }
+        };
+
+        if !self.compiler.inline_sourced_files {
+            return vec![];
+        }
+
+        let contents = match std::fs::read(&resolved) {
+            Ok(contents) => contents,
+            Err(err) => {
+                self.error_on_node(format!("cannot read {}: {err}", resolved.display()), path);
+                return vec![];
+            }
+        };
+
+        let offset = self.compiler.span_offset();
+        self.compiler
+            .add_file(&resolved.display().to_string(), &contents);
+        // lex the compiler's copy, in case add_file normalized line endings
+        let contents = self.compiler.source[offset..].to_vec();
+        let (tokens, err) = lex(&contents, offset);
+        if err.is_err() {
+            self.error_on_node(
+                format!("error lexing sourced file {}", resolved.display()),
+                path,
+            );
+            return vec![];
+        }
+
+        let saved_tokens = std::mem::replace(&mut self.tokens, tokens);
+        let block = self.block(BlockContext::Bare);
+        self.tokens = saved_tokens;
+
+        let AstNode::Block(block_id) = self.compiler.ast_nodes[block.0] else {
+            panic!("internal error: expected block");
+        };
+        self.compiler.blocks[block_id.0].nodes.clone()
+    }
+
+    pub fn module_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"module");
+
+        let name = self.name();
+        let block = self.block(BlockContext::Curlies);
+        let span_end = self.get_span_end(block);
+        self.create_node(AstNode::Module { name, block }, span_start, span_end)
+    }
+
+    pub fn use_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"use");
+
+        if !self.is_name() {
+            return self.error("expected: module path after 'use'");
+        }
+
+        // `use foo/bar baz` flattens to the segment path foo -> bar -> baz; slash-separated
+        // and space-separated segments address nested modules the same way
+        let mut path = vec![];
+        while self.is_name() {
+            path.push(self.name());
+            if self.tokens.peek_token() == Token::ForwardSlash {
+                self.tokens.advance();
+            }
+        }
+
+        let span_end = self.get_span_end(*path.last().expect("use path is not empty"));
+        self.create_node(AstNode::Use { path }, span_start, span_end)
+    }
+
+    pub fn export_env_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        // consume 'export', '-' and 'env'
+        self.tokens.advance();
+        self.tokens.advance();
+        self.tokens.advance();
+
+        let block = self.block(BlockContext::Curlies);
+        let span_end = self.get_span_end(block);
+        self.create_node(AstNode::ExportEnv { block }, span_start, span_end)
+    }
+
     pub fn is_operator(&mut self) -> bool {
         let (token, span) = self.tokens.peek();
 
@@ -1532,10 +2618,16 @@ impl Parser {
             | Token::DashEquals
             | Token::AsteriskEquals
             | Token::ForwardSlashEquals
-            | Token::PlusPlusEquals => true,
+            | Token::PlusPlusEquals
+            | Token::QuestionQuestionMark => true,
             Token::Bareword => {
                 let op = self.compiler.get_span_contents_manual(span.start, span.end);
-                op == b"mod" || op == b"in" || op == b"and" || op == b"xor" || op == b"or"
+                op == b"mod"
+                    || op == b"in"
+                    || op == b"and"
+                    || op == b"xor"
+                    || op == b"or"
+                    || self.peek_bit_operator().is_some()
             }
             _ => false,
         }
@@ -1589,6 +2681,10 @@ impl Parser {
         self.tokens.peek_token() == Token::Dollar
     }
 
+    pub fn is_caret(&mut self) -> bool {
+        self.tokens.peek_token() == Token::Caret
+    }
+
     pub fn is_comment(&mut self) -> bool {
         self.tokens.peek_token() == Token::Comment
     }
@@ -1669,6 +2765,7 @@ impl Parser {
         self.is_simple_expression()
             || self.is_keyword(b"if")
             || self.is_keyword(b"match")
+            || self.is_keyword(b"try")
             || self.is_keyword(b"where")
     }
 
@@ -1681,17 +2778,52 @@ impl Parser {
             || self.is_lparen()
             || self.is_dot()
             || self.is_dollar()
+            || self.is_caret()
             || self.is_keyword(b"true")
             || self.is_keyword(b"false")
             || self.is_keyword(b"null")
             || self.is_name()
     }
 
+    /// Report an unclosed delimiter, labeling the opening delimiter's span
+    ///
+    /// Used for error recovery: the caller stops consuming tokens at a statement boundary (or
+    /// EOF) so that whatever follows the unclosed construct still parses.
+    pub fn unclosed_delimiter(&mut self, closing: &str, open_span: Span) -> NodeId {
+        let node_id = self.create_node(AstNode::Garbage, open_span.start, open_span.end);
+        self.error_on_node(format!("unclosed delimiter: expected '{closing}'"), node_id);
+        node_id
+    }
+
+    /// Check whether the upcoming token is a keyword that can only start a statement
+    pub fn is_statement_keyword(&mut self) -> bool {
+        self.is_keyword(b"def")
+            || self.is_keyword(b"let")
+            || self.is_keyword(b"mut")
+            || self.is_keyword(b"while")
+            || self.is_keyword(b"for")
+            || self.is_keyword(b"loop")
+            || self.is_keyword(b"return")
+            || self.is_keyword(b"continue")
+            || self.is_keyword(b"break")
+            || self.is_keyword(b"alias")
+    }
+
     pub fn error_on_node(&mut self, message: impl Into<String>, node_id: NodeId) {
-        self.compiler.errors.push(SourceError {
+        self.compiler.push_error(SourceError {
             message: message.into(),
             node_id,
             severity: Severity::Error,
+            code: None,
+        });
+    }
+
+    pub fn warning_on_node(&mut self, message: impl Into<String>, node_id: NodeId) {
+        self.compiler.push_error(SourceError {
+            message: message.into(),
+            node_id,
+            severity: Severity::Warning,
+            code: None,
         });
     }
 
@@ -1703,10 +2835,15 @@ impl Parser {
         }
 
         let node_id = self.create_node(AstNode::Garbage, span.start, span.end);
-        self.compiler.errors.push(SourceError {
+        if token == Token::Eof {
+            // zero-width placeholder not backed by any user-written token
+            self.compiler.set_synthetic(node_id);
+        }
+        self.compiler.push_error(SourceError {
             message: message.into(),
             node_id,
             severity: Severity::Error,
+            code: None,
         });
 
         node_id
@@ -1825,11 +2962,35 @@ impl Parser {
     }
 
     pub fn skip_newlines(&mut self) {
-        while self.is_newline() {
-            self.tokens.advance();
+        loop {
+            if self.is_newline() {
+                self.tokens.advance();
+            } else if self.is_line_continuation() {
+                // consume the backslash along with the line break it hides
+                self.tokens.advance();
+                self.tokens.advance();
+            } else {
+                break;
+            }
         }
     }
 
+    /// Check whether the upcoming tokens form a `\` line continuation: a backslash with the
+    /// line break immediately after it
+    pub fn is_line_continuation(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        if token != Token::Backslash {
+            return false;
+        }
+
+        let pos = self.tokens.pos();
+        self.tokens.advance();
+        let (next, next_span) = self.tokens.peek();
+        self.tokens.set_pos(pos);
+
+        next == Token::Newline && next_span.start == span.end
+    }
+
     fn get_rollback_point(&self) -> RollbackPoint {
         self.compiler.get_rollback_point(self.tokens.pos())
     }
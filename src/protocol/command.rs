@@ -1,15 +1,41 @@
+/// Structured signature of a command, for call checking, completion and help
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    /// Positional parameter names, in order
+    pub positional: Vec<String>,
+    /// Long flag names, without the leading `--`
+    pub flags: Vec<String>,
+}
+
 pub trait Command: CommandClone {
     fn name(&self) -> &str;
+
+    /// The command's parsed signature, if one is available
+    fn signature(&self) -> Option<&Signature> {
+        None
+    }
 }
 
 #[derive(Clone)]
 pub struct Declaration {
     name: String,
+    signature: Option<Signature>,
 }
 
 impl Declaration {
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self {
+            name,
+            signature: None,
+        }
+    }
+
+    pub fn with_signature(name: String, signature: Signature) -> Self {
+        Self {
+            name,
+            signature: Some(signature),
+        }
     }
 }
 
@@ -17,6 +43,10 @@ impl Command for Declaration {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
 }
 
 // Cloning implementation taken from Nushell
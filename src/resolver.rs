@@ -1,4 +1,4 @@
-use crate::protocol::{Command, Declaration};
+use crate::protocol::{Command, Declaration, Signature};
 use crate::{
     compiler::Compiler,
     errors::{Severity, SourceError},
@@ -25,6 +25,8 @@ pub struct Frame {
     pub variables: HashMap<Vec<u8>, NodeId>,
     pub type_decls: HashMap<Vec<u8>, NodeId>,
     pub decls: HashMap<Vec<u8>, NodeId>,
+    /// Modules defined in this scope, mapping a module's name to the scope frame of its body
+    pub modules: HashMap<Vec<u8>, ScopeId>,
     /// Node that defined the scope frame (e.g., a block or overlay)
     pub node_id: NodeId,
 }
@@ -36,6 +38,7 @@ impl Frame {
             variables: HashMap::new(),
             type_decls: HashMap::new(),
             decls: HashMap::new(),
+            modules: HashMap::new(),
             node_id,
         }
     }
@@ -123,6 +126,11 @@ pub struct Resolver<'a> {
     pub decl_resolution: HashMap<NodeId, DeclId>,
     /// Errors encountered during name binding
     pub errors: Vec<SourceError>,
+    /// Depths of scope_stack at which the currently entered closures' bodies start, innermost
+    /// last. Used to tell captured variables apart from a closure's own.
+    closure_boundaries: Vec<usize>,
+    /// How many module bodies the resolver is currently inside
+    module_depth: usize,
 }
 
 impl<'a> Resolver<'a> {
@@ -139,6 +147,8 @@ impl<'a> Resolver<'a> {
             decl_nodes: vec![],
             decl_resolution: HashMap::new(),
             errors: vec![],
+            closure_boundaries: vec![],
+            module_depth: 0,
         }
     }
 
@@ -191,7 +201,13 @@ impl<'a> Resolver<'a> {
                 .map(|(name, id)| format!("{0}: {id:?}", String::from_utf8_lossy(name)))
                 .collect();
 
-            if vars.is_empty() && types.is_empty() && decls.is_empty() {
+            let mut modules: Vec<String> = scope
+                .modules
+                .iter()
+                .map(|(name, id)| format!("{0}: {id:?}", String::from_utf8_lossy(name)))
+                .collect();
+
+            if vars.is_empty() && types.is_empty() && decls.is_empty() && modules.is_empty() {
                 result.push_str(" (empty)\n");
                 continue;
             }
@@ -215,6 +231,12 @@ impl<'a> Resolver<'a> {
                 let line_decl = format!("      decls: [ {0} ]\n", decls.join(", "));
                 result.push_str(&line_decl);
             }
+
+            if !modules.is_empty() {
+                modules.sort();
+                let line_module = format!("    modules: [ {0} ]\n", modules.join(", "));
+                result.push_str(&line_module);
+            }
         }
 
         if !self.errors.is_empty() {
@@ -243,6 +265,12 @@ impl<'a> Resolver<'a> {
         match self.compiler.ast_nodes[node_id.0] {
             AstNode::Variable => self.resolve_variable(node_id),
             AstNode::Call { ref parts } => self.resolve_call(node_id, parts),
+            AstNode::ExternalCall { ref parts } => {
+                // the command name is not a declaration; resolve only the arguments
+                for part in &parts[1..] {
+                    self.resolve_node(*part);
+                }
+            }
             AstNode::Block(block_id) => self.resolve_block(node_id, block_id, None),
             AstNode::Closure { params, block } => {
                 // making sure the closure parameters and body end up in the same scope frame
@@ -258,7 +286,9 @@ impl<'a> Resolver<'a> {
                     panic!("internal error: closure's body is not a block");
                 };
 
+                self.closure_boundaries.push(self.scope_stack.len());
                 self.resolve_block(block, block_id, closure_scope);
+                self.closure_boundaries.pop();
             }
             AstNode::Def {
                 name,
@@ -300,9 +330,50 @@ impl<'a> Resolver<'a> {
             } => {
                 self.define_decl(new_name, node_id);
             }
+            AstNode::ExportEnv { block } => {
+                if self.module_depth == 0 {
+                    self.errors.push(SourceError {
+                        message: "export-env is only allowed inside a module".to_string(),
+                        node_id,
+                        severity: Severity::Error,
+                        code: None,
+                    });
+                }
+                self.resolve_node(block);
+            }
+            AstNode::Module { name, block } => {
+                let AstNode::Block(block_id) = self.compiler.ast_nodes[block.0] else {
+                    panic!("internal error: module's body is not a block");
+                };
+                let body = self
+                    .compiler
+                    .blocks
+                    .get(block_id.0)
+                    .expect("internal error: missing block");
+
+                // resolve the body in its own frame, then keep the frame around under the
+                // module's name so `use` can bring its declarations into scope later
+                self.enter_scope(block);
+                self.module_depth += 1;
+                for inner_node_id in &body.nodes {
+                    self.resolve_node(*inner_node_id);
+                }
+                self.module_depth -= 1;
+                let module_scope = self.exit_scope();
+
+                let module_name = self.compiler.get_span_contents(name).to_vec();
+                let current_scope_id = self
+                    .scope_stack
+                    .last()
+                    .expect("internal error: missing scope frame id");
+                self.scope[current_scope_id.0]
+                    .modules
+                    .insert(module_name, module_scope);
+            }
+            AstNode::Use { ref path } => self.resolve_use(path),
             AstNode::Params(ref params) => {
                 for param in params {
-                    let AstNode::Param { name, ty } = self.compiler.ast_nodes[param.0] else {
+                    let AstNode::Param { name, ty, .. } = self.compiler.ast_nodes[param.0] else {
                         panic!("param is not a param");
                     };
                     self.define_variable(name, false);
@@ -348,9 +419,23 @@ impl<'a> Resolver<'a> {
             AstNode::Loop { block } => {
                 self.resolve_node(block);
             }
-            AstNode::BinaryOp { lhs, op: _, rhs } => {
+            AstNode::Paren(inner) => self.resolve_node(inner),
+            AstNode::Spread(inner) => self.resolve_node(inner),
+            AstNode::BinaryOp { lhs, op, rhs } => {
                 self.resolve_node(lhs);
                 self.resolve_node(rhs);
+
+                if matches!(
+                    self.compiler.ast_nodes[op.0],
+                    AstNode::Assignment
+                        | AstNode::AddAssignment
+                        | AstNode::SubtractAssignment
+                        | AstNode::MultiplyAssignment
+                        | AstNode::DivideAssignment
+                        | AstNode::AppendAssignment
+                ) {
+                    self.check_captured_mut_assignment(node_id, lhs);
+                }
             }
             AstNode::Range { lhs, rhs } => {
                 self.resolve_node(lhs);
@@ -373,10 +458,20 @@ impl<'a> Resolver<'a> {
                     self.resolve_node(*val);
                 }
             }
-            AstNode::MemberAccess { target, field } => {
+            AstNode::MemberAccess { target, field, .. } => {
                 self.resolve_node(target);
                 self.resolve_node(field);
             }
+            AstNode::Redirection { expr, target, .. } => {
+                self.resolve_node(expr);
+                if let Some(target) = target {
+                    self.resolve_node(target);
+                }
+            }
+            AstNode::CondSequence { lhs, rhs, .. } => {
+                self.resolve_node(lhs);
+                self.resolve_node(rhs);
+            }
             AstNode::If {
                 condition,
                 then_block,
@@ -388,17 +483,44 @@ impl<'a> Resolver<'a> {
                     self.resolve_node(block);
                 }
             }
+            AstNode::Try { try_block, catch } => {
+                self.resolve_node(try_block);
+                if let Some(catch) = catch {
+                    self.resolve_node(catch);
+                }
+            }
             AstNode::Match {
                 target,
                 ref match_arms,
             } => {
                 self.resolve_node(target);
                 for (arm_lhs, arm_rhs) in match_arms {
-                    self.resolve_node(*arm_lhs);
+                    // each arm gets its own scope so that pattern bindings are visible to
+                    // the guard and the arm's result only
+                    self.enter_scope(*arm_lhs);
+
+                    let (pattern, guard) = match self.compiler.ast_nodes[arm_lhs.0] {
+                        AstNode::MatchGuard { pattern, guard } => (pattern, Some(guard)),
+                        _ => (*arm_lhs, None),
+                    };
+
+                    if matches!(self.compiler.ast_nodes[pattern.0], AstNode::Variable) {
+                        // a variable pattern binds the matched value
+                        self.define_variable(pattern, false);
+                    } else {
+                        self.resolve_node(pattern);
+                    }
+
+                    if let Some(guard) = guard {
+                        self.resolve_node(guard);
+                    }
                     self.resolve_node(*arm_rhs);
+
+                    self.exit_scope();
                 }
             }
             AstNode::Statement(node) => self.resolve_node(node),
+            AstNode::Source { path, .. } => self.resolve_node(path),
             AstNode::Type { name, args, .. } => {
                 self.resolve_type(name);
                 if let Some(args) = args {
@@ -448,6 +570,11 @@ impl<'a> Resolver<'a> {
     pub fn resolve_variable(&mut self, unbound_node_id: NodeId) {
         let var_name = trim_var_name(self.compiler.get_span_contents(unbound_node_id));
 
+        // $in refers to the pipeline input and has no declaration to resolve to
+        if var_name == b"in" {
+            return;
+        }
+
         if let Some(node_id) = self.find_variable(var_name) {
             let var_id = self
                 .var_resolution
@@ -460,6 +587,7 @@ impl<'a> Resolver<'a> {
                 message: format!("variable `{}` not found", String::from_utf8_lossy(var_name)),
                 node_id: unbound_node_id,
                 severity: Severity::Error,
+                code: None,
             })
         }
     }
@@ -485,6 +613,7 @@ impl<'a> Resolver<'a> {
                 message: format!("type `{}` not found", String::from_utf8_lossy(type_name)),
                 node_id: unbound_node_id,
                 severity: Severity::Error,
+                code: None,
             })
         }
     }
@@ -525,6 +654,80 @@ impl<'a> Resolver<'a> {
         }
     }
 
+    /// Resolve a `use` statement's segment path through nested module scopes
+    ///
+    /// The first segment is looked up like any other name; every intermediate segment must name
+    /// a module defined inside the previous one. The leaf segment may name a submodule, whose
+    /// declarations are all brought into the current scope frame, or a single declaration of the
+    /// module reached so far, which is imported alone.
+    pub fn resolve_use(&mut self, path: &[NodeId]) {
+        let first = *path.first().expect("internal error: empty use path");
+        let first_name = self.compiler.get_span_contents(first);
+
+        let Some(mut module_scope) = self.find_module(first_name) else {
+            self.errors.push(SourceError {
+                message: format!(
+                    "module `{}` not found",
+                    String::from_utf8_lossy(first_name)
+                ),
+                node_id: first,
+                severity: Severity::Error,
+                code: None,
+            });
+            return;
+        };
+
+        let current_scope_id = *self
+            .scope_stack
+            .last()
+            .expect("internal error: missing scope frame id");
+
+        let mut parent_name = first_name;
+        for (i, segment) in path.iter().copied().enumerate().skip(1) {
+            let segment_name = self.compiler.get_span_contents(segment);
+            if let Some(inner_scope) = self.scope[module_scope.0].modules.get(segment_name) {
+                module_scope = *inner_scope;
+                parent_name = segment_name;
+                continue;
+            }
+
+            let is_leaf = i == path.len() - 1;
+            if is_leaf {
+                if let Some(decl_name_id) =
+                    self.scope[module_scope.0].decls.get(segment_name).copied()
+                {
+                    self.scope[current_scope_id.0]
+                        .decls
+                        .insert(segment_name.to_vec(), decl_name_id);
+                    return;
+                }
+            }
+
+            self.errors.push(SourceError {
+                message: format!(
+                    "module `{}` has no {} `{}`",
+                    String::from_utf8_lossy(parent_name),
+                    if is_leaf { "export" } else { "submodule" },
+                    String::from_utf8_lossy(segment_name)
+                ),
+                node_id: segment,
+                severity: Severity::Error,
+                code: None,
+            });
+            return;
+        }
+
+        // bring all of the leaf module's declarations into the current scope frame
+        let exports: Vec<(Vec<u8>, NodeId)> = self.scope[module_scope.0]
+            .decls
+            .iter()
+            .map(|(name, id)| (name.clone(), *id))
+            .collect();
+        for (name, decl_name_id) in exports {
+            self.scope[current_scope_id.0].decls.insert(name, decl_name_id);
+        }
+    }
+
     pub fn resolve_block(
         &mut self,
         node_id: NodeId,
@@ -623,7 +826,11 @@ impl<'a> Resolver<'a> {
         // TODO: Deduplicate code with define_variable()
         let decl_name = self.compiler.get_span_contents(decl_name_id);
         let decl_name = trim_decl_name(decl_name).to_vec();
-        let decl = Declaration::new(String::from_utf8_lossy(&decl_name).to_string());
+        let name = String::from_utf8_lossy(&decl_name).to_string();
+        let decl = match self.decl_signature(&name, decl_node_id) {
+            Some(signature) => Declaration::with_signature(name, signature),
+            None => Declaration::new(name),
+        };
 
         let current_scope_id = self
             .scope_stack
@@ -642,6 +849,90 @@ impl<'a> Resolver<'a> {
         self.decl_resolution.insert(decl_name_id, decl_id);
     }
 
+    /// Build the structured signature of a declaration from its signature AST
+    ///
+    /// Only command definitions carry one; aliases (and anything else) have no signature.
+    fn decl_signature(&self, name: &str, decl_node_id: NodeId) -> Option<Signature> {
+        let AstNode::Def { params, .. } = self.compiler.get_node(decl_node_id) else {
+            return None;
+        };
+        let AstNode::Params(param_ids) = self.compiler.get_node(*params) else {
+            return None;
+        };
+
+        let mut positional = vec![];
+        let mut flags = vec![];
+        for param_id in param_ids {
+            let AstNode::Param { name, .. } = self.compiler.get_node(*param_id) else {
+                continue;
+            };
+            let text = String::from_utf8_lossy(self.compiler.get_span_contents(*name));
+            match text.strip_prefix("--") {
+                Some(flag) => flags.push(flag.to_string()),
+                None => positional.push(text.to_string()),
+            }
+        }
+
+        Some(Signature {
+            name: name.to_string(),
+            positional,
+            flags,
+        })
+    }
+
+    /// Error on an assignment to a `mut` variable declared outside the innermost closure
+    ///
+    /// Closures capture by value, so mutating a captured variable would only ever change the
+    /// closure's copy; it is forbidden outright. Reading a captured `mut` stays allowed, as does
+    /// assigning to a `mut` declared inside the closure itself.
+    fn check_captured_mut_assignment(&mut self, assignment_id: NodeId, lhs: NodeId) {
+        let Some(boundary) = self.closure_boundaries.last().copied() else {
+            return;
+        };
+        if !matches!(self.compiler.ast_nodes[lhs.0], AstNode::Variable) {
+            return;
+        }
+
+        let var_name = trim_var_name(self.compiler.get_span_contents(lhs));
+        for (pos, scope_id) in self.scope_stack.iter().enumerate().rev() {
+            let Some(def_node) = self.scope[scope_id.0].variables.get(var_name) else {
+                continue;
+            };
+            let def_node = *def_node;
+            if pos >= boundary {
+                // declared inside the closure; mutating it is fine
+                return;
+            }
+
+            let is_mutable = self
+                .var_resolution
+                .get(&def_node)
+                .map(|var_id| self.variables[var_id.0].is_mutable)
+                .unwrap_or(false);
+            if is_mutable {
+                self.errors.push(SourceError {
+                    message: format!(
+                        "cannot assign to `{}` captured by the closure; closures capture by value",
+                        String::from_utf8_lossy(var_name)
+                    ),
+                    node_id: assignment_id,
+                    severity: Severity::Error,
+                    code: None,
+                });
+                self.errors.push(SourceError {
+                    message: format!(
+                        "variable `{}` declared here",
+                        String::from_utf8_lossy(var_name)
+                    ),
+                    node_id: def_node,
+                    severity: Severity::Note,
+                    code: None,
+                });
+            }
+            return;
+        }
+    }
+
     pub fn find_variable(&self, var_name: &[u8]) -> Option<NodeId> {
         for scope_id in self.scope_stack.iter().rev() {
             if let Some(id) = self.scope[scope_id.0].variables.get(var_name) {
@@ -662,6 +953,16 @@ impl<'a> Resolver<'a> {
         None
     }
 
+    pub fn find_module(&self, module_name: &[u8]) -> Option<ScopeId> {
+        for scope_id in self.scope_stack.iter().rev() {
+            if let Some(id) = self.scope[scope_id.0].modules.get(module_name) {
+                return Some(*id);
+            }
+        }
+
+        None
+    }
+
     pub fn find_decl(&self, var_name: &[u8]) -> Option<NodeId> {
         // TODO: Deduplicate code with find_variable()
         for scope_id in self.scope_stack.iter().rev() {
@@ -675,6 +976,12 @@ impl<'a> Resolver<'a> {
 }
 
 fn trim_var_name(name: &[u8]) -> &[u8] {
+    // a rest parameter's name carries the `...` marker; the variable is the bare name
+    let name = if name.starts_with(b"...") && name.len() > 3 {
+        &name[3..]
+    } else {
+        name
+    };
     if name.starts_with(b"$") && name.len() > 1 {
         &name[1..]
     } else {
@@ -78,6 +78,57 @@ fn test_node_output() {
     });
 }
 
+/// Compare each corpus fixture against its committed `.golden` file
+///
+/// Unlike the insta snapshots above, the goldens are plain files sitting next to their
+/// fixtures, so a behavior change across the whole grammar shows up as an ordinary diff.
+/// Run with `UPDATE_GOLDENS=1` to rewrite them after an intended change.
+#[test]
+fn test_corpus_goldens() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut fixtures: Vec<_> = std::fs::read_dir(&corpus)
+        .expect("corpus directory exists")
+        .map(|entry| entry.expect("corpus directory is readable").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "nu"))
+        .collect();
+    fixtures.sort();
+    assert!(
+        !fixtures.is_empty(),
+        "no fixtures found in {}",
+        corpus.display()
+    );
+
+    let update = std::env::var_os("UPDATE_GOLDENS").is_some();
+    let mut failures = vec![];
+
+    for fixture in fixtures {
+        let actual = evaluate_example(&fixture);
+        let golden = fixture.with_extension("golden");
+
+        if update {
+            std::fs::write(&golden, &actual).expect("golden file is writable");
+            continue;
+        }
+
+        match std::fs::read_to_string(&golden) {
+            // normalize newlines so checkouts with CRLF translation still compare equal
+            Ok(expected) if expected.replace("\r\n", "\n") == actual => {}
+            Ok(_) => failures.push(format!(
+                "{}: output differs from {}",
+                fixture.display(),
+                golden.display()
+            )),
+            Err(_) => failures.push(format!("{}: missing golden file", fixture.display())),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "corpus mismatches (run with UPDATE_GOLDENS=1 to accept):\n{}",
+        failures.join("\n")
+    );
+}
+
 #[test]
 fn test_lexer() {
     insta::glob!("../tests/lex", "*.nu", |path| {
@@ -1,10 +1,10 @@
 //! See typechecking.md in the contributing/ folder for more information on
 //! how the typechecker works
 
-use crate::compiler::Compiler;
+use crate::compiler::{builtin_command, Compiler, CustomTypeId};
 use crate::errors::{Severity, SourceError};
-use crate::parser::{AstNode, NodeId};
-use crate::resolver::{TypeDecl, TypeDeclId};
+use crate::parser::{AstNode, NodeId, RedirectMode};
+use crate::resolver::{TypeDecl, TypeDeclId, VarId};
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
@@ -19,6 +19,7 @@ pub struct InOutType {
 }
 
 /// A type variable used for type inference
+#[derive(Debug, Clone)]
 pub struct TypeVar {
     lower_bound: TypeId,
     upper_bound: TypeId,
@@ -62,7 +63,16 @@ pub enum Type {
     Closure,
     List(TypeId),
     Stream(TypeId),
+    /// A range of numbers (e.g. `1..10`) with the given element type. Distinct from `list`:
+    /// using a range where a list is expected is a type error rather than an auto-conversion,
+    /// so the mismatch surfaces at the range instead of silently draining it.
+    Range(TypeId),
     Record(RecordTypeId),
+    /// A record whose fields are fixed by the language rather than spelled out in user source
+    /// (see [`BuiltinRecord`]), so it doesn't need an entry in `record_types`.
+    BuiltinRecord(BuiltinRecord),
+    /// A caller-defined literal type (see [`Compiler::register_literal_suffix`])
+    Custom(CustomTypeId),
     /// Union type. OneOf types should not be nested and should have at least two elements.
     /// They can contain allof types.
     OneOf(OneOfId),
@@ -78,6 +88,10 @@ pub enum Type {
 pub struct Types {
     pub types: Vec<Type>,
     pub node_types: Vec<TypeId>,
+    pub record_types: Vec<Vec<(NodeId, TypeId)>>,
+    pub oneof_types: Vec<HashSet<TypeId>>,
+    pub allof_types: Vec<HashSet<TypeId>>,
+    pub type_vars: Vec<TypeVar>,
     pub errors: Vec<SourceError>,
 }
 
@@ -104,6 +118,53 @@ pub const BYTE_STREAM_TYPE: TypeId = TypeId(13);
 pub const ERROR_TYPE: TypeId = TypeId(14);
 pub const TOP_TYPE: TypeId = TypeId(15);
 pub const BOTTOM_TYPE: TypeId = TypeId(16);
+pub const ERROR_VALUE_TYPE: TypeId = TypeId(17);
+pub const COMPLETE_OUTPUT_TYPE: TypeId = TypeId(18);
+pub const SYS_OUTPUT_TYPE: TypeId = TypeId(19);
+pub const SYS_HOST_TYPE: TypeId = TypeId(20);
+
+/// Builtin record types whose fields are fixed by the language rather than spelled out in user
+/// source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinRecord {
+    /// The structured error value passed to a `catch` closure
+    ErrorValue,
+    /// The output record of the `complete` command
+    CompleteOutput,
+    /// The output record of the `sys` command
+    SysOutput,
+    /// The `host` field of the `sys` output
+    SysHost,
+}
+
+impl BuiltinRecord {
+    /// The record's fields, sorted by field name
+    pub fn fields(self) -> &'static [(&'static str, TypeId)] {
+        match self {
+            BuiltinRecord::ErrorValue => &[
+                ("debug", STRING_TYPE),
+                ("json", STRING_TYPE),
+                ("msg", STRING_TYPE),
+                ("raw", ANY_TYPE),
+                ("rendered", STRING_TYPE),
+            ],
+            BuiltinRecord::CompleteOutput => &[
+                ("exit_code", INT_TYPE),
+                ("stderr", STRING_TYPE),
+                ("stdout", STRING_TYPE),
+            ],
+            BuiltinRecord::SysOutput => &[("host", SYS_HOST_TYPE)],
+            BuiltinRecord::SysHost => &[("name", STRING_TYPE), ("os", STRING_TYPE)],
+        }
+    }
+
+    pub fn field_type(self, field_name: &[u8]) -> Option<TypeId> {
+        self.fields()
+            .iter()
+            .find(|(name, _)| name.as_bytes() == field_name)
+            .map(|(_, ty)| *ty)
+    }
+}
 
 pub struct Typechecker<'a> {
     /// Immutable reference to a compiler after the name binding pass
@@ -129,6 +190,9 @@ pub struct Typechecker<'a> {
     pub decl_types: Vec<Vec<InOutType>>,
     /// Errors encountered during type checking
     pub errors: Vec<SourceError>,
+    /// `if` nodes in statement position, whose value is discarded. An else-less `if` is only
+    /// optional-typed when its value is actually consumed.
+    statement_ifs: HashSet<NodeId>,
 }
 
 impl<'a> Typechecker<'a> {
@@ -154,6 +218,10 @@ impl<'a> Typechecker<'a> {
                 Type::Error,
                 Type::Top,
                 Type::Bottom,
+                Type::BuiltinRecord(BuiltinRecord::ErrorValue),
+                Type::BuiltinRecord(BuiltinRecord::CompleteOutput),
+                Type::BuiltinRecord(BuiltinRecord::SysOutput),
+                Type::BuiltinRecord(BuiltinRecord::SysHost),
             ],
             node_types: vec![UNKNOWN_TYPE; compiler.ast_nodes.len()],
             record_types: Vec::new(),
@@ -169,6 +237,7 @@ impl<'a> Typechecker<'a> {
                 compiler.decls.len()
             ],
             errors: vec![],
+            statement_ifs: HashSet::new(),
         }
     }
 
@@ -176,6 +245,10 @@ impl<'a> Typechecker<'a> {
         Types {
             types: self.types,
             node_types: self.node_types,
+            record_types: self.record_types,
+            oneof_types: self.oneof_types,
+            allof_types: self.allof_types,
+            type_vars: self.type_vars,
             errors: self.errors,
         }
     }
@@ -217,21 +290,52 @@ impl<'a> Typechecker<'a> {
             let last = self.compiler.ast_nodes.len() - 1;
             let last_node_id = NodeId(last);
             self.typecheck_node(last_node_id);
+            self.canonicalize_types(false);
+        }
+    }
+
+    /// Resolve all remaining type variables so consumers never see an unresolved type
+    ///
+    /// Runs at the end of typecheck(): every variable is replaced by its solved lower bound,
+    /// and anything irreducibly open after that becomes `any`. This keeps `type_of` stable
+    /// for hover output and other tooling. In strict mode, nodes whose type had to default
+    /// to `any` additionally get a warning.
+    pub fn canonicalize_types(&mut self, strict: bool) {
+        for i in 0..self.type_vars.len() {
+            let var = &self.type_vars[i];
+            let bound = var.lower_bound;
+            // a variable bounded by itself cannot be reduced; the sweep below defaults it
+            if matches!(self.types[bound.0], Type::Var(var_id) if var_id.0 == i) {
+                continue;
+            }
+            let cleaned = self.eliminate_type_vars(bound, TypeVarId(0), true);
+            self.types[bound.0] = self.types[cleaned.0];
+        }
 
-            for i in 0..self.type_vars.len() {
-                let var = &self.type_vars[i];
-                let bound = var.lower_bound;
-                let cleaned = self.eliminate_type_vars(bound, TypeVarId(0), true);
-                self.types[bound.0] = self.types[cleaned.0];
+        for i in 0..self.types.len() {
+            if let Type::Var(var_id) = &self.types[i] {
+                let bound = self.type_vars[var_id.0].lower_bound;
+                self.types[i] = self.types[bound.0];
             }
+        }
 
-            for i in 0..self.types.len() {
-                if let Type::Var(var_id) = &self.types[i] {
-                    let bound = self.type_vars[var_id.0].lower_bound;
-                    self.types[i] = self.types[bound.0];
+        if strict {
+            for i in 0..self.node_types.len() {
+                if matches!(self.types[self.node_types[i].0], Type::Var(_)) {
+                    self.warning(
+                        "cannot infer the type of this expression; assuming any",
+                        NodeId(i),
+                    );
                 }
             }
         }
+
+        // a variable whose bound is itself still open cannot be reduced further
+        for ty in self.types.iter_mut() {
+            if matches!(ty, Type::Var(_)) {
+                *ty = Type::Any;
+            }
+        }
     }
 
     /// Get type ID of a node
@@ -254,7 +358,7 @@ impl<'a> Typechecker<'a> {
                 // Params are not supposed to be evaluated
                 self.set_node_type_id(node_id, FORBIDDEN_TYPE);
             }
-            AstNode::Param { name, ty } => {
+            AstNode::Param { name, ty, .. } => {
                 if let Some(ty) = ty {
                     let ty_id = self.typecheck_type(ty);
 
@@ -334,6 +438,31 @@ impl<'a> Typechecker<'a> {
             AstNode::Alias { new_name, old_name } => {
                 self.typecheck_alias(new_name, old_name, node_id)
             }
+            AstNode::Source { path, .. } => {
+                // the sourced statements, if inlined, typecheck on their own; the statement
+                // itself produces no value
+                self.typecheck_expr(path, STRING_TYPE);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::ExportEnv { block } => {
+                // the env block typechecks like any other block; it produces no value itself
+                self.typecheck_node(block);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::Module { block, .. } => {
+                // the body's definitions typecheck like any others; the statement has no value
+                self.typecheck_node(block);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::Use { .. } => {
+                // name binding happens in the resolver; there is nothing left to check here
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::Statement(inner) => {
+                // a `;`-terminated expression; its value is discarded
+                self.typecheck_expr(inner, TOP_TYPE);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
             AstNode::For {
                 variable,
                 range,
@@ -347,7 +476,8 @@ impl<'a> Typechecker<'a> {
                     .var_resolution
                     .get(&variable)
                     .expect("missing resolved variable");
-                if let Type::List(type_id) = self.type_of(range) {
+                // ranges iterate as streams of their element type
+                if let Type::List(type_id) | Type::Stream(type_id) | Type::Range(type_id) = self.type_of(range) {
                     self.variable_types[var_id.0] = type_id;
                     self.set_node_type_id(variable, type_id);
                 } else {
@@ -378,6 +508,11 @@ impl<'a> Typechecker<'a> {
                 // TODO make sure we're in a loop
                 self.set_node_type_id(node_id, NONE_TYPE);
             }
+            AstNode::If { .. } => {
+                // an `if` whose value is discarded may omit `else`
+                self.statement_ifs.insert(node_id);
+                self.typecheck_expr(node_id, TOP_TYPE);
+            }
             _ if self.is_expr(node_id) => {
                 self.typecheck_expr(node_id, TOP_TYPE);
             }
@@ -396,6 +531,7 @@ impl<'a> Typechecker<'a> {
             AstNode::Null => NOTHING_TYPE,
             AstNode::Int => INT_TYPE,
             AstNode::Float => FLOAT_TYPE,
+            AstNode::CustomLiteral { type_id } => self.push_type(Type::Custom(type_id)),
             AstNode::True | AstNode::False => BOOL_TYPE,
             AstNode::String => STRING_TYPE,
             AstNode::List(ref items) => {
@@ -445,9 +581,35 @@ impl<'a> Typechecker<'a> {
             }
             AstNode::Pipeline(pipeline_id) => {
                 let pipeline = &self.compiler.pipelines[pipeline_id.0];
-                let expressions = pipeline.get_expressions();
-                for inner in expressions {
-                    self.typecheck_expr(*inner, TOP_TYPE);
+                let expressions = pipeline.get_expressions().clone();
+                let mut input_type = NONE_TYPE;
+                for inner in &expressions {
+                    if let AstNode::Call { ref parts } = self.compiler.ast_nodes[inner.0] {
+                        let parts = parts.clone();
+                        if input_type != NONE_TYPE && self.ignores_pipeline_input(*inner, &parts) {
+                            self.warning("pipeline input is ignored by this command", *inner);
+                        }
+                        if let Some(ty) =
+                            self.typecheck_higher_order_call(&parts, *inner, input_type)
+                        {
+                            self.set_node_type_id(*inner, ty);
+                            input_type = ty;
+                            continue;
+                        }
+                        if let Some(ty) =
+                            self.typecheck_list_builtin_call(&parts, *inner, input_type)
+                        {
+                            self.set_node_type_id(*inner, ty);
+                            input_type = ty;
+                            continue;
+                        }
+                        if let Some(ty) = self.typecheck_into_call(&parts, *inner, input_type) {
+                            self.set_node_type_id(*inner, ty);
+                            input_type = ty;
+                            continue;
+                        }
+                    }
+                    input_type = self.typecheck_expr(*inner, TOP_TYPE);
                 }
 
                 // pipeline type is the type of the last expression, since blocks
@@ -466,14 +628,28 @@ impl<'a> Typechecker<'a> {
                 CLOSURE_TYPE
             }
             AstNode::BinaryOp { lhs, op, rhs } => self.typecheck_binary_op(lhs, op, rhs),
-            AstNode::Variable => {
-                let var_id = self
-                    .compiler
-                    .var_resolution
-                    .get(&node_id)
-                    .expect("missing resolved variable");
+            AstNode::Range { lhs, rhs } => {
+                let lhs_type = self.typecheck_expr(lhs, NUMBER_TYPE);
+                let rhs_type = self.typecheck_expr(rhs, NUMBER_TYPE);
+
+                // float endpoints produce float elements; inclusive vs exclusive doesn't matter
+                let elem_type = if lhs_type == FLOAT_TYPE || rhs_type == FLOAT_TYPE {
+                    FLOAT_TYPE
+                } else if lhs_type == INT_TYPE && rhs_type == INT_TYPE {
+                    INT_TYPE
+                } else {
+                    NUMBER_TYPE
+                };
 
-                self.variable_types[var_id.0]
+                self.push_type(Type::Range(elem_type))
+            }
+            AstNode::Variable => {
+                if let Some(var_id) = self.compiler.var_resolution.get(&node_id) {
+                    self.variable_types[var_id.0]
+                } else {
+                    // $in (the pipeline input) has no declaration; its type is not tracked yet
+                    ANY_TYPE
+                }
             }
             AstNode::If {
                 condition,
@@ -495,12 +671,47 @@ impl<'a> Typechecker<'a> {
                     types.insert(then_type_id);
                     types.insert(else_type_id);
                     self.create_oneof(types)
+                } else if self.statement_ifs.contains(&node_id) {
+                    // in statement position the missing branch's nothing is discarded anyway
+                    NOTHING_TYPE
+                } else {
+                    // the value is consumed: when the condition is false it is nothing
+                    let mut types = HashSet::new();
+                    types.insert(then_type_id);
+                    types.insert(NOTHING_TYPE);
+                    self.create_oneof(types)
+                }
+            }
+            AstNode::Try { try_block, catch } => {
+                let try_type = self.typecheck_block(try_block, expected);
+
+                let mut types = HashSet::new();
+                types.insert(try_type);
+
+                if let Some(catch) = catch {
+                    types.insert(self.typecheck_catch(catch));
                 } else {
-                    // If there's no else block, the if expression is a statement
-                    NONE_TYPE
+                    // a failing try without a catch yields nothing
+                    types.insert(NOTHING_TYPE);
                 }
+
+                self.create_oneof(types)
             }
             AstNode::Call { ref parts } => self.typecheck_call(parts, node_id),
+            AstNode::ExternalCall { ref parts } => {
+                let parts = parts.clone();
+                self.set_node_type_id(parts[0], STRING_TYPE);
+                for part in &parts[1..] {
+                    if matches!(self.compiler.ast_nodes[part.0], AstNode::Name) {
+                        self.set_node_type_id(*part, STRING_TYPE);
+                    } else {
+                        self.typecheck_expr(*part, TOP_TYPE);
+                    }
+                }
+
+                // externals consume and produce byte streams
+                BYTE_STREAM_TYPE
+            }
             AstNode::Match {
                 ref target,
                 ref match_arms,
@@ -513,6 +724,104 @@ impl<'a> Typechecker<'a> {
                     self.create_oneof(output_types)
                 }
             }
+            AstNode::Paren(inner) => self.typecheck_expr(inner, expected),
+            AstNode::MemberAccess {
+                target,
+                field,
+                optional,
+            } => {
+                let target_type = self.typecheck_expr(target, TOP_TYPE);
+                self.set_node_type_id(field, STRING_TYPE);
+
+                let field_name = self.compiler.get_span_contents(field).to_vec();
+                match self.types[target_type.0] {
+                    Type::Record(rec_id) => {
+                        let found = self.record_types[rec_id.0]
+                            .iter()
+                            .find(|(name, _)| {
+                                self.compiler.get_span_contents(*name) == field_name
+                            })
+                            .map(|(_, ty)| *ty);
+
+                        self.member_field_type(found, optional, &field_name, target_type, field)
+                    }
+                    Type::BuiltinRecord(rec) => {
+                        let found = rec.field_type(&field_name);
+
+                        self.member_field_type(found, optional, &field_name, target_type, field)
+                    }
+                    // cell-path access on a union succeeds only if every member supports it
+                    Type::OneOf(id) => {
+                        let members: Vec<TypeId> = self.oneof_types[id.0].iter().copied().collect();
+                        let mut field_types = HashSet::new();
+                        let mut all_support = true;
+                        for member in members {
+                            let found = match self.types[member.0] {
+                                Type::Record(rec_id) => self.record_types[rec_id.0]
+                                    .iter()
+                                    .find(|(name, _)| {
+                                        self.compiler.get_span_contents(*name) == field_name
+                                    })
+                                    .map(|(_, ty)| *ty),
+                                Type::BuiltinRecord(rec) => rec.field_type(&field_name),
+                                // imprecise members may have any field
+                                Type::Any | Type::Unknown | Type::Top => Some(ANY_TYPE),
+                                _ => None,
+                            };
+                            match found {
+                                Some(ty) => {
+                                    field_types.insert(ty);
+                                }
+                                None if optional => {
+                                    field_types.insert(NOTHING_TYPE);
+                                }
+                                None => all_support = false,
+                            }
+                        }
+                        if all_support {
+                            self.create_oneof(field_types)
+                        } else {
+                            self.error(
+                                format!(
+                                    "unknown field '{}' of {}",
+                                    String::from_utf8_lossy(&field_name),
+                                    self.type_to_string(target_type)
+                                ),
+                                field,
+                            );
+                            ERROR_TYPE
+                        }
+                    }
+                    // without a known record type we can't say more than any
+                    _ => ANY_TYPE,
+                }
+            }
+            AstNode::Redirection { expr, mode, target } => {
+                let expr_type = self.typecheck_expr(expr, TOP_TYPE);
+
+                if let Some(target) = target {
+                    self.typecheck_expr(target, STRING_TYPE);
+                }
+
+                match mode {
+                    // stderr-only redirections leave stdout flowing through the pipeline
+                    RedirectMode::Err | RedirectMode::ErrAppend => expr_type,
+                    // stdout is written to a file or discarded, so nothing flows on
+                    _ => NOTHING_TYPE,
+                }
+            }
+            AstNode::CondSequence { lhs, rhs, .. } => {
+                // the sides are sequenced on the left command's exit status, not on a
+                // `bool` it produced, so neither side is constrained like `and`/`or`
+                let lhs_type = self.typecheck_expr(lhs, TOP_TYPE);
+                let rhs_type = self.typecheck_expr(rhs, TOP_TYPE);
+
+                // whichever side ran last produces the value
+                let mut types = HashSet::new();
+                types.insert(lhs_type);
+                types.insert(rhs_type);
+                self.create_oneof(types)
+            }
             _ => {
                 self.error(
                     format!(
@@ -527,25 +836,81 @@ impl<'a> Typechecker<'a> {
         self.set_node_type_id(node_id, ty_id);
 
         if !self.constrain_subtype(ty_id, expected) {
-            self.error(
-                format!(
-                    "Expected {}, got {}",
-                    self.type_to_string(expected),
-                    self.type_to_string(ty_id)
-                ),
-                node_id,
-            );
+            let message = self.describe_mismatch(expected, ty_id);
+            self.error(message, node_id);
         }
 
         ty_id
     }
 
+    /// Human-friendly description of a type mismatch
+    ///
+    /// Shared by every mismatch site so the wording stays consistent. For list and record
+    /// mismatches, the description additionally drills into the specific differing element
+    /// or field.
+    pub fn describe_mismatch(&self, expected: TypeId, found: TypeId) -> String {
+        let base = format!(
+            "expected `{}`, found `{}`",
+            self.type_to_string(expected),
+            self.type_to_string(found)
+        );
+
+        match (&self.types[expected.0], &self.types[found.0]) {
+            (Type::List(exp), Type::List(fnd)) | (Type::Stream(exp), Type::Stream(fnd)) => {
+                if self.type_to_string(*exp) == self.type_to_string(*fnd) {
+                    base
+                } else {
+                    format!("{base}: list element: {}", self.describe_mismatch(*exp, *fnd))
+                }
+            }
+            (Type::Record(exp), Type::Record(fnd)) => {
+                let expected_fields = &self.record_types[exp.0];
+                let found_fields = &self.record_types[fnd.0];
+
+                for (name, exp_ty) in expected_fields {
+                    let field_name = self.compiler.get_span_contents(*name);
+                    let Some((_, fnd_ty)) = found_fields
+                        .iter()
+                        .find(|(fnd_name, _)| self.compiler.get_span_contents(*fnd_name) == field_name)
+                    else {
+                        return format!(
+                            "{base}: missing field `{}`",
+                            String::from_utf8_lossy(field_name)
+                        );
+                    };
+                    if self.type_to_string(*exp_ty) != self.type_to_string(*fnd_ty) {
+                        return format!(
+                            "{base}: field `{}`: {}",
+                            String::from_utf8_lossy(field_name),
+                            self.describe_mismatch(*exp_ty, *fnd_ty)
+                        );
+                    }
+                }
+                for (name, _) in found_fields {
+                    let field_name = self.compiler.get_span_contents(*name);
+                    if !expected_fields
+                        .iter()
+                        .any(|(exp_name, _)| self.compiler.get_span_contents(*exp_name) == field_name)
+                    {
+                        return format!(
+                            "{base}: unexpected field `{}`",
+                            String::from_utf8_lossy(field_name)
+                        );
+                    }
+                }
+                base
+            }
+            _ => base,
+        }
+    }
+
     fn is_expr(&mut self, node_id: NodeId) -> bool {
         matches!(
             self.compiler.ast_nodes[node_id.0],
             AstNode::Null
                 | AstNode::Int
                 | AstNode::Float
+                | AstNode::CustomLiteral { .. }
                 | AstNode::True
                 | AstNode::False
                 | AstNode::String
@@ -556,12 +921,52 @@ impl<'a> Typechecker<'a> {
                 | AstNode::Pipeline(_)
                 | AstNode::Closure { .. }
                 | AstNode::BinaryOp { .. }
+                | AstNode::Range { .. }
                 | AstNode::If { .. }
                 | AstNode::Call { .. }
+                | AstNode::ExternalCall { .. }
                 | AstNode::Match { .. }
+                | AstNode::MemberAccess { .. }
+                | AstNode::Paren(_)
+                | AstNode::Redirection { .. }
+                | AstNode::CondSequence { .. }
+                | AstNode::Try { .. }
         )
     }
 
+    /// Type of a member access, given the looked-up field type (if the field exists)
+    fn member_field_type(
+        &mut self,
+        found: Option<TypeId>,
+        optional: bool,
+        field_name: &[u8],
+        target_type: TypeId,
+        node_id: NodeId,
+    ) -> TypeId {
+        match (found, optional) {
+            (Some(field_type), false) => field_type,
+            (Some(field_type), true) => {
+                // the field may still be absent at runtime
+                let mut types = HashSet::new();
+                types.insert(field_type);
+                types.insert(NOTHING_TYPE);
+                self.create_oneof(types)
+            }
+            (None, true) => NOTHING_TYPE,
+            (None, false) => {
+                self.error(
+                    format!(
+                        "unknown field '{}' of {}",
+                        String::from_utf8_lossy(field_name),
+                        self.type_to_string(target_type)
+                    ),
+                    node_id,
+                );
+                ERROR_TYPE
+            }
+        }
+    }
+
     fn typecheck_match(
         &mut self,
         target: &NodeId,
@@ -571,14 +976,85 @@ impl<'a> Typechecker<'a> {
         self.typecheck_expr(*target, TOP_TYPE);
 
         let mut output_types = HashSet::new();
+        // an arm that matches any value at all, for the exhaustiveness check below
+        let mut has_catch_all = false;
+        let mut all_literal_arms = true;
         // typecheck each node
         let target_id = self.type_id_of(*target);
         for (match_node, result_node) in match_arms {
-            self.typecheck_node(*match_node);
-            self.typecheck_expr(*result_node, expected);
+            let (pattern_node, guard_node) = match self.compiler.ast_nodes[match_node.0] {
+                AstNode::MatchGuard { pattern, guard } => (pattern, Some(guard)),
+                _ => (*match_node, None),
+            };
+
+            let is_binding = matches!(self.compiler.ast_nodes[pattern_node.0], AstNode::Variable);
+            if is_binding {
+                // a variable pattern binds the matched value
+                let var_id = self
+                    .compiler
+                    .var_resolution
+                    .get(&pattern_node)
+                    .expect("missing resolved variable");
+                self.variable_types[var_id.0] = target_id;
+                self.set_node_type_id(pattern_node, target_id);
+            } else if self.is_expr(pattern_node) {
+                self.typecheck_expr(pattern_node, TOP_TYPE);
+            } else {
+                self.typecheck_node(pattern_node);
+            }
+
+            if let Some(guard) = guard_node {
+                // the guard is a condition and may use the pattern's bindings
+                self.typecheck_expr(guard, BOOL_TYPE);
+            }
+            if *match_node != pattern_node {
+                self.set_node_type_id(*match_node, self.type_id_of(pattern_node));
+            }
+
+            // within the arm body the scrutinee is known to be what the pattern matched;
+            // narrow a variable scrutinee to the literal's type (or a range pattern's
+            // element type) while the body typechecks
+            let narrowed = match self.compiler.ast_nodes[pattern_node.0] {
+                AstNode::Int
+                | AstNode::Float
+                | AstNode::String
+                | AstNode::True
+                | AstNode::False => Some(self.type_id_of(pattern_node)),
+                AstNode::Range { .. } => match self.type_of(pattern_node) {
+                    Type::Range(elem) => Some(elem),
+                    _ => None,
+                },
+                _ => {
+                    all_literal_arms = false;
+                    None
+                }
+            };
+            let target_var = self.compiler.var_resolution.get(target).copied();
+            if let (Some(var_id), Some(narrowed)) = (target_var, narrowed) {
+                let saved = self.variable_types[var_id.0];
+                self.variable_types[var_id.0] = narrowed;
+                self.typecheck_expr(*result_node, expected);
+                self.variable_types[var_id.0] = saved;
+            } else {
+                self.typecheck_expr(*result_node, expected);
+            }
+
+            // wildcard and variable patterns match any target
+            let is_wildcard = matches!(
+                self.compiler.ast_nodes[pattern_node.0],
+                AstNode::String | AstNode::Name
+            ) && self.compiler.get_span_contents(pattern_node) == b"_";
+            if is_binding || is_wildcard {
+                // a guarded catch-all may still fall through
+                if guard_node.is_none() {
+                    has_catch_all = true;
+                }
+                self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
+                continue;
+            }
 
-            let match_id = self.type_id_of(*match_node);
-            match (self.type_of(*target), self.type_of(*match_node)) {
+            let match_id = self.type_id_of(pattern_node);
+            match (self.type_of(*target), self.type_of(pattern_node)) {
                 // First is of type Any which will always match
                 (Type::Any, _) => {
                     self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
@@ -608,6 +1084,10 @@ impl<'a> Typechecker<'a> {
                         self.error("The target to be matched against and the possible types of the matched arm are completely disjoint", *match_node);
                     }
                 }
+                // a range pattern matches scalars of its element type
+                (target_ty, Type::Range(elem)) if self.is_type_compatible(target_ty, self.types[elem.0]) => {
+                    self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
+                }
                 // Check if the two types can be matched
                 (target_id, match_id) if self.is_type_compatible(target_id, match_id) => {
                     self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
@@ -617,6 +1097,15 @@ impl<'a> Typechecker<'a> {
                 }
             }
         }
+
+        // finitely many literal or range arms can never cover every int
+        if target_id == INT_TYPE && all_literal_arms && !has_catch_all && !match_arms.is_empty() {
+            self.warning(
+                "match on an int value is not exhaustive; add a wildcard arm",
+                *target,
+            );
+        }
+
         output_types
     }
 
@@ -674,7 +1163,7 @@ impl<'a> Typechecker<'a> {
                         self.typecheck_expr(lhs, STRING_TYPE);
                         BOOL_TYPE
                     }
-                    Type::List(elem_ty) => {
+                    Type::List(elem_ty) | Type::Range(elem_ty) => {
                         self.typecheck_expr(lhs, elem_ty);
                         BOOL_TYPE
                     }
@@ -693,6 +1182,50 @@ impl<'a> Typechecker<'a> {
                 self.typecheck_expr(rhs, BOOL_TYPE);
                 BOOL_TYPE
             }
+            AstNode::BitAnd
+            | AstNode::BitOr
+            | AstNode::BitXor
+            | AstNode::BitShiftLeft
+            | AstNode::BitShiftRight => {
+                // integer-only; typecheck_expr labels a non-int operand with the error
+                self.typecheck_expr(lhs, INT_TYPE);
+                self.typecheck_expr(rhs, INT_TYPE);
+                INT_TYPE
+            }
+            AstNode::Coalesce => {
+                let lhs_ty = self.typecheck_expr(lhs, TOP_TYPE);
+                let rhs_ty = self.typecheck_expr(rhs, TOP_TYPE);
+
+                match self.types[lhs_ty.0] {
+                    // the default always wins
+                    Type::Nothing => rhs_ty,
+                    Type::OneOf(id) if self.oneof_types[id.0].contains(&NOTHING_TYPE) => {
+                        // the left side minus `nothing`, which is covered by the default
+                        let mut types: HashSet<TypeId> = self.oneof_types[id.0]
+                            .iter()
+                            .copied()
+                            .filter(|ty| *ty != NOTHING_TYPE)
+                            .collect();
+                        types.insert(rhs_ty);
+                        self.create_oneof(types)
+                    }
+                    // imprecise types may still turn out to be nothing at runtime
+                    Type::Any | Type::Unknown | Type::Top | Type::Bottom | Type::Error
+                    | Type::Var(_) => {
+                        let mut types = HashSet::new();
+                        types.insert(lhs_ty);
+                        types.insert(rhs_ty);
+                        self.create_oneof(types)
+                    }
+                    _ => {
+                        self.warning("left side of '??' can never be nothing", lhs);
+                        let mut types = HashSet::new();
+                        types.insert(lhs_ty);
+                        types.insert(rhs_ty);
+                        self.create_oneof(types)
+                    }
+                }
+            }
             AstNode::Plus => {
                 let mut types = HashSet::new();
                 types.insert(STRING_TYPE);
@@ -713,7 +1246,7 @@ impl<'a> Typechecker<'a> {
                     if !rhs_bottom && self.is_subtype(rhs_ty, STRING_TYPE) {
                         if !self.constrain_subtype(lhs_ty, STRING_TYPE) {
                             self.error(
-                                format!("Expected string, got {}", self.type_to_string(lhs_ty)),
+                                self.describe_mismatch(STRING_TYPE, lhs_ty),
                                 lhs,
                             );
                         }
@@ -721,7 +1254,7 @@ impl<'a> Typechecker<'a> {
                     } else if !rhs_bottom && self.is_subtype(rhs_ty, NUMBER_TYPE) {
                         if !self.constrain_subtype(lhs_ty, NUMBER_TYPE) {
                             self.error(
-                                format!("Expected number, got {}", self.type_to_string(lhs_ty)),
+                                self.describe_mismatch(NUMBER_TYPE, lhs_ty),
                                 lhs,
                             );
                         }
@@ -848,89 +1381,656 @@ impl<'a> Typechecker<'a> {
         );
     }
 
-    fn typecheck_call(&mut self, parts: &[NodeId], node_id: NodeId) -> TypeId {
-        if let Some(decl_id) = self.compiler.decl_resolution.get(&node_id) {
-            let num_name_parts = self.compiler.decls[decl_id.0].name().split(' ').count();
-            let decl_node_id = self.compiler.decl_nodes[decl_id.0];
-            let AstNode::Def {
-                type_params,
-                params,
-                ..
-            } = self.compiler.get_node(decl_node_id)
-            else {
-                panic!("Internal error: Expected def")
-            };
-            let AstNode::Params(params) = self.compiler.get_node(*params) else {
-                panic!("Internal error: Expected params")
+    fn typecheck_call(&mut self, parts: &[NodeId], node_id: NodeId) -> TypeId {
+        if let Some(ty) = self.typecheck_do_call(parts, node_id) {
+            return ty;
+        }
+        if let Some(decl_id) = self.compiler.decl_resolution.get(&node_id) {
+            let num_name_parts = self.compiler.decls[decl_id.0].name().split(' ').count();
+            let decl_node_id = self.compiler.decl_nodes[decl_id.0];
+            let AstNode::Def {
+                type_params,
+                params,
+                ..
+            } = self.compiler.get_node(decl_node_id)
+            else {
+                panic!("Internal error: Expected def")
+            };
+            let signature_node = *params;
+            let AstNode::Params(params) = self.compiler.get_node(*params) else {
+                panic!("Internal error: Expected params")
+            };
+
+            let type_substs = if let Some(type_params) = type_params {
+                let AstNode::Params(type_params) = self.compiler.get_node(*type_params) else {
+                    panic!("Internal error: expected type params");
+                };
+                let mut type_substs = HashMap::new();
+                for type_param in type_params.iter() {
+                    let type_decl_id = self.compiler.type_resolution[type_param];
+                    let var = self.new_typevar(BOTTOM_TYPE, TOP_TYPE);
+                    type_substs.insert(type_decl_id, var);
+                }
+                type_substs
+            } else {
+                HashMap::new()
+            };
+
+            // every command accepts --help/-h in addition to its declared flag parameters, so
+            // add the auto-flags first and dedup an explicit --help declaration against them
+            let mut known_flags: Vec<&[u8]> = vec![b"--help", b"-h"];
+            let mut flag_params = vec![];
+            let mut positional_params = vec![];
+            let mut rest_param = None;
+            for param in params {
+                let AstNode::Param { name, .. } = self.compiler.get_node(*param) else {
+                    panic!("Internal error: Expected param")
+                };
+                if matches!(self.compiler.ast_nodes[name.0], AstNode::FlagLong) {
+                    let flag_name = self.compiler.get_span_contents(*name);
+                    if !known_flags.contains(&flag_name) {
+                        known_flags.push(flag_name);
+                        // the bare name is what record fields spread as (`--foo` -> `foo`)
+                        flag_params.push((&flag_name[2..], *param));
+                    }
+                } else if self.compiler.get_span_contents(*name).starts_with(b"...") {
+                    rest_param = Some(*param);
+                } else {
+                    positional_params.push(*param);
+                }
+            }
+
+            let mut args = vec![];
+            for part in &parts[num_name_parts..] {
+                match self.compiler.ast_nodes[part.0] {
+                    AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup => {
+                        let flag_name = self.compiler.get_span_contents(*part);
+                        if !known_flags.contains(&flag_name) {
+                            self.error(
+                                format!("unknown flag '{}'", String::from_utf8_lossy(flag_name)),
+                                *part,
+                            );
+                        }
+                        self.set_node_type_id(*part, BOOL_TYPE);
+                    }
+                    AstNode::Spread(inner) => {
+                        self.typecheck_spread(*part, inner, &flag_params);
+                    }
+                    _ => args.push(*part),
+                }
+            }
+
+            let num_args = args.len();
+            if num_args < positional_params.len() {
+                self.error(
+                    format!(
+                        "Expected {} argument(s), got {}",
+                        positional_params.len(),
+                        num_args
+                    ),
+                    node_id,
+                );
+            } else if num_args > positional_params.len() && rest_param.is_none() {
+                // a rest parameter would absorb the surplus; without one the first extra
+                // argument is the error site, with the signature as a related label
+                let command = self.compiler.decls[decl_id.0].name().to_string();
+                self.error(
+                    format!(
+                        "command `{}` takes {} positional argument(s) but {} were given",
+                        command,
+                        positional_params.len(),
+                        num_args
+                    ),
+                    args[positional_params.len()],
+                );
+                self.note(format!("command `{command}` declared here"), signature_node);
+            }
+            for (param, arg) in positional_params.iter().zip(&args) {
+                let expected = self.type_id_of(*param);
+                let expected = self.subst(expected, &type_substs);
+                if matches!(self.compiler.ast_nodes[arg.0], AstNode::Name) {
+                    self.set_node_type_id(*arg, STRING_TYPE);
+                    if !self.constrain_subtype(STRING_TYPE, expected) {
+                        self.error(
+                            self.describe_mismatch(expected, STRING_TYPE),
+                            *arg,
+                        );
+                    }
+                } else {
+                    self.typecheck_expr(*arg, expected);
+                }
+            }
+            if num_args > positional_params.len() {
+                // Typecheck extra arguments too
+                for arg in &args[positional_params.len()..] {
+                    if matches!(self.compiler.ast_nodes[arg.0], AstNode::Name) {
+                        self.set_node_type_id(*arg, STRING_TYPE);
+                    } else {
+                        self.typecheck_expr(*arg, TOP_TYPE);
+                    }
+                }
+            }
+
+            // TODO base this on pipeline input type
+            let out_types = self.decl_types[decl_id.0]
+                .clone()
+                .iter()
+                .map(|io| self.subst(io.out_type, &type_substs))
+                .collect();
+            self.create_oneof(out_types)
+        } else {
+            // external call
+            for part in &parts[1..] {
+                match self.compiler.ast_nodes[part.0] {
+                    AstNode::Name => self.set_node_type_id(*part, STRING_TYPE),
+                    // externals accept any flag, and so any record spread
+                    AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup => {
+                        self.set_node_type_id(*part, BOOL_TYPE)
+                    }
+                    AstNode::Spread(inner) => {
+                        self.typecheck_expr(inner, TOP_TYPE);
+                        self.set_node_type_id(*part, NONE_TYPE);
+                    }
+                    _ => {
+                        self.typecheck_expr(*part, TOP_TYPE);
+                    }
+                }
+            }
+
+            // builtin commands with a precisely known output record (trimmed because a call
+            // name's span can include trailing whitespace when it ends its line)
+            match self.compiler.get_span_contents(parts[0]).trim_ascii() {
+                b"complete" => COMPLETE_OUTPUT_TYPE,
+                b"sys" => SYS_OUTPUT_TYPE,
+                _ => BYTE_STREAM_TYPE,
+            }
+        }
+    }
+
+    /// Whether a pipeline stage is a call to a command that ignores its pipeline input
+    ///
+    /// For user declarations this is read off the declared input types: a command whose every
+    /// input type is `nothing` ignores its input, while one that accepts `any` input never
+    /// triggers the warning. Calls that don't resolve to a declaration are checked against a
+    /// short list of builtins known to take no pipeline input.
+    fn ignores_pipeline_input(&self, node_id: NodeId, parts: &[NodeId]) -> bool {
+        if let Some(decl_id) = self.compiler.decl_resolution.get(&node_id) {
+            let in_out_types = &self.decl_types[decl_id.0];
+            return !in_out_types.is_empty()
+                && in_out_types.iter().all(|io| io.in_type == NOTHING_TYPE);
+        }
+
+        // trimmed because a call name's span can include trailing whitespace at end of line
+        matches!(
+            self.compiler.get_span_contents(parts[0]).trim_ascii(),
+            b"cd" | b"exit"
+        )
+    }
+
+    /// Typecheck a call to one of the builtin higher-order commands (`each`, `where`, `reduce`)
+    ///
+    /// These commands thread the element type of their pipeline input into their closure argument:
+    /// `each` maps `list<T>` to `list<U>` with a `T -> U` closure, `where` keeps the element type
+    /// and requires a `bool` closure, and `reduce` produces the closure's result type. Returns
+    /// None if the call is not one of these commands (or is shadowed by a user-defined command),
+    /// in which case it is typechecked as a regular call.
+    fn typecheck_higher_order_call(
+        &mut self,
+        parts: &[NodeId],
+        node_id: NodeId,
+        input_type: TypeId,
+    ) -> Option<TypeId> {
+        // user-defined commands shadow the builtin signatures
+        if self.compiler.decl_resolution.contains_key(&node_id) {
+            return None;
+        }
+
+        let name = self.compiler.get_span_contents(parts[0]).to_vec();
+        if !builtin_command(&name).is_some_and(|builtin| builtin.higher_order) {
+            return None;
+        }
+
+        let closure_id = *parts
+            .iter()
+            .skip(1)
+            .find(|part| matches!(self.compiler.ast_nodes[part.0], AstNode::Closure { .. }))?;
+
+        // typecheck the non-closure arguments as usual
+        for part in &parts[1..] {
+            if *part == closure_id {
+                continue;
+            }
+            if matches!(self.compiler.ast_nodes[part.0], AstNode::Name) {
+                self.set_node_type_id(*part, STRING_TYPE);
+            } else {
+                self.typecheck_expr(*part, TOP_TYPE);
+            }
+        }
+
+        let elem_type = match self.types[input_type.0] {
+            Type::List(elem) | Type::Stream(elem) | Type::Range(elem) => elem,
+            // records are not iterated field by field; the whole record is a single item
+            Type::Record(_) | Type::BuiltinRecord(_) => {
+                self.warning(
+                    format!(
+                        "'{}' treats a record as a single item; use transpose to iterate \
+                         its fields",
+                        String::from_utf8_lossy(&name)
+                    ),
+                    node_id,
+                );
+                input_type
+            }
+            // imprecise inputs (or no input at all) may still be collections at runtime
+            Type::None
+            | Type::Any
+            | Type::Unknown
+            | Type::Top
+            | Type::Bottom
+            | Type::Error
+            | Type::Var(_) => ANY_TYPE,
+            // per Nushell semantics a scalar is treated as a single-item stream
+            _ => {
+                self.warning(
+                    format!(
+                        "'{}' over a value of type {} iterates it as a single-item stream",
+                        String::from_utf8_lossy(&name),
+                        self.type_to_string(input_type)
+                    ),
+                    node_id,
+                );
+                input_type
+            }
+        };
+
+        let AstNode::Closure { params, block } = self.compiler.ast_nodes[closure_id.0] else {
+            panic!("internal error: expected closure");
+        };
+
+        if let Some(params_id) = params {
+            self.typecheck_node(params_id);
+
+            // bind the first (element) parameter to the input's element type, unless the
+            // user annotated it explicitly
+            let AstNode::Params(ref param_nodes) = self.compiler.ast_nodes[params_id.0] else {
+                panic!("internal error: expected params");
+            };
+            if let Some(param_id) = param_nodes.first().copied() {
+                let AstNode::Param { name, ty, .. } = self.compiler.ast_nodes[param_id.0] else {
+                    panic!("internal error: expected param");
+                };
+                if ty.is_none() {
+                    let var_id = self
+                        .compiler
+                        .var_resolution
+                        .get(&name)
+                        .expect("missing resolved variable");
+                    self.variable_types[var_id.0] = elem_type;
+                    self.set_node_type_id(param_id, elem_type);
+                }
+            }
+        }
+
+        let body_type = self.typecheck_block(block, TOP_TYPE);
+        self.set_node_type_id(closure_id, CLOSURE_TYPE);
+
+        let out_type = match name.as_slice() {
+            b"each" => self.push_type(Type::List(body_type)),
+            b"where" => {
+                if !self.constrain_subtype(body_type, BOOL_TYPE) {
+                    self.error(
+                        format!(
+                            "where closure must return bool, got {}",
+                            self.type_to_string(body_type)
+                        ),
+                        closure_id,
+                    );
+                }
+                self.push_type(Type::List(elem_type))
+            }
+            b"reduce" => body_type,
+            _ => unreachable!("checked above"),
+        };
+        Some(out_type)
+    }
+
+    /// Typecheck the list builtins (`append`, `prepend`, `insert`) that thread their pipeline
+    /// input's element type into the result
+    ///
+    /// New elements matching the input's element type keep the narrow `list<T>`; a mismatched
+    /// element widens the result to `list<any>` rather than erroring.
+    fn typecheck_list_builtin_call(
+        &mut self,
+        parts: &[NodeId],
+        node_id: NodeId,
+        input_type: TypeId,
+    ) -> Option<TypeId> {
+        // user-defined commands shadow the builtin signatures
+        if self.compiler.decl_resolution.contains_key(&node_id) {
+            return None;
+        }
+
+        let name = self.compiler.get_span_contents(parts[0]).to_vec();
+        if !matches!(name.as_slice(), b"append" | b"prepend" | b"insert") {
+            return None;
+        }
+
+        // without a known list input, fall back to the generic call path
+        let elem_type = match self.types[input_type.0] {
+            Type::List(elem) | Type::Stream(elem) | Type::Range(elem) => elem,
+            _ => return None,
+        };
+
+        let mut args = parts[1..].iter();
+        // `insert` takes the position first; the remaining arguments are the new elements
+        if name.as_slice() == b"insert" {
+            if let Some(index) = args.next() {
+                self.typecheck_expr(*index, INT_TYPE);
+            }
+        }
+
+        let mut widened = false;
+        for part in args {
+            if matches!(
+                self.compiler.ast_nodes[part.0],
+                AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup
+            ) {
+                self.set_node_type_id(*part, BOOL_TYPE);
+                continue;
+            }
+            let arg_type = self.typecheck_expr(*part, TOP_TYPE);
+            if !self.is_subtype(arg_type, elem_type) {
+                widened = true;
+            }
+        }
+
+        Some(if widened {
+            LIST_ANY_TYPE
+        } else {
+            self.push_type(Type::List(elem_type))
+        })
+    }
+
+    /// Typecheck the `into` conversion builtins, whose result type is the conversion target
+    ///
+    /// `"5" | into int` flows `int` down the pipeline; lists and streams convert elementwise.
+    /// A conversion that cannot apply to the input type is an error. Targets without a
+    /// corresponding type here (e.g. `into datetime`) fall back to the generic call path.
+    fn typecheck_into_call(
+        &mut self,
+        parts: &[NodeId],
+        node_id: NodeId,
+        input_type: TypeId,
+    ) -> Option<TypeId> {
+        // user-defined commands shadow the builtin signatures
+        if self.compiler.decl_resolution.contains_key(&node_id) {
+            return None;
+        }
+
+        if self.compiler.get_span_contents(parts[0]) != b"into" {
+            return None;
+        }
+        let target_id = *parts.get(1)?;
+        if !matches!(self.compiler.ast_nodes[target_id.0], AstNode::Name) {
+            return None;
+        }
+        let target = match self.compiler.get_span_contents(target_id) {
+            b"int" => INT_TYPE,
+            b"float" => FLOAT_TYPE,
+            b"string" => STRING_TYPE,
+            b"bool" => BOOL_TYPE,
+            b"binary" => BINARY_TYPE,
+            _ => return None,
+        };
+        self.set_node_type_id(target_id, STRING_TYPE);
+
+        // typecheck the remaining arguments as usual
+        for part in &parts[2..] {
+            if matches!(
+                self.compiler.ast_nodes[part.0],
+                AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup
+            ) {
+                self.set_node_type_id(*part, BOOL_TYPE);
+            } else {
+                self.typecheck_expr(*part, TOP_TYPE);
+            }
+        }
+
+        let (from, elementwise) = match self.types[input_type.0] {
+            Type::List(elem) | Type::Stream(elem) => (elem, true),
+            _ => (input_type, false),
+        };
+
+        if !self.can_convert(from, target) {
+            self.error(
+                format!(
+                    "cannot convert {} into {}",
+                    self.type_to_string(from),
+                    self.type_to_string(target)
+                ),
+                node_id,
+            );
+        }
+
+        Some(if elementwise {
+            self.push_type(Type::List(target))
+        } else {
+            target
+        })
+    }
+
+    /// Typecheck `do`, which invokes its closure argument with the remaining arguments
+    ///
+    /// The arguments are checked against the closure's parameters and the call is typed as
+    /// the closure's body type, so `do {|x| $x + 1} 5` comes out as int. An un-annotated
+    /// parameter takes its argument's type; an annotated one checks the argument against
+    /// the annotation. Returns None when the call is not a `do` with a closure argument,
+    /// falling back to the generic call path.
+    fn typecheck_do_call(&mut self, parts: &[NodeId], node_id: NodeId) -> Option<TypeId> {
+        // user-defined commands shadow the builtin signature
+        if self.compiler.decl_resolution.contains_key(&node_id) {
+            return None;
+        }
+        if self.compiler.get_span_contents(parts[0]).trim_ascii() != b"do" {
+            return None;
+        }
+        let closure_id = *parts.get(1)?;
+        let AstNode::Closure { params, block } = self.compiler.ast_nodes[closure_id.0] else {
+            return None;
+        };
+
+        self.set_node_type_id(parts[0], STRING_TYPE);
+
+        let mut args = vec![];
+        for part in &parts[2..] {
+            match self.compiler.ast_nodes[part.0] {
+                AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup => {
+                    self.set_node_type_id(*part, BOOL_TYPE);
+                }
+                _ => args.push(*part),
+            }
+        }
+
+        let mut param_ids = vec![];
+        if let Some(params_id) = params {
+            self.typecheck_node(params_id);
+            let AstNode::Params(ref param_nodes) = self.compiler.ast_nodes[params_id.0] else {
+                panic!("internal error: expected params");
             };
+            param_ids = param_nodes.clone();
+        }
 
-            let type_substs = if let Some(type_params) = type_params {
-                let AstNode::Params(type_params) = self.compiler.get_node(*type_params) else {
-                    panic!("Internal error: expected type params");
-                };
-                let mut type_substs = HashMap::new();
-                for type_param in type_params.iter() {
-                    let type_decl_id = self.compiler.type_resolution[type_param];
-                    let var = self.new_typevar(BOTTOM_TYPE, TOP_TYPE);
-                    type_substs.insert(type_decl_id, var);
+        if param_ids.len() != args.len() {
+            self.error(
+                format!(
+                    "closure expects {} argument(s), got {}",
+                    param_ids.len(),
+                    args.len()
+                ),
+                node_id,
+            );
+        }
+
+        for (param_id, arg) in param_ids.iter().zip(&args) {
+            let AstNode::Param { name, ty, .. } = self.compiler.ast_nodes[param_id.0] else {
+                panic!("internal error: expected param");
+            };
+            let expected = if ty.is_some() {
+                self.type_id_of(*param_id)
+            } else {
+                TOP_TYPE
+            };
+            let arg_type = if matches!(self.compiler.ast_nodes[arg.0], AstNode::Name) {
+                self.set_node_type_id(*arg, STRING_TYPE);
+                if !self.constrain_subtype(STRING_TYPE, expected) {
+                    self.error(self.describe_mismatch(expected, STRING_TYPE), *arg);
                 }
-                type_substs
+                STRING_TYPE
             } else {
-                HashMap::new()
+                self.typecheck_expr(*arg, expected)
             };
 
-            let num_args = parts.len() - num_name_parts;
-            if params.len() != num_args {
+            if ty.is_none() {
+                // an un-annotated parameter takes its argument's type
+                let var_id = self
+                    .compiler
+                    .var_resolution
+                    .get(&name)
+                    .expect("missing resolved variable");
+                self.variable_types[var_id.0] = arg_type;
+                self.set_node_type_id(*param_id, arg_type);
+            }
+        }
+        // typecheck surplus arguments too
+        for arg in args.iter().skip(param_ids.len()) {
+            if matches!(self.compiler.ast_nodes[arg.0], AstNode::Name) {
+                self.set_node_type_id(*arg, STRING_TYPE);
+            } else {
+                self.typecheck_expr(*arg, TOP_TYPE);
+            }
+        }
+
+        let body_type = self.typecheck_block(block, TOP_TYPE);
+        self.set_node_type_id(closure_id, CLOSURE_TYPE);
+        Some(body_type)
+    }
+
+    /// Typecheck a record spread into a command's named arguments (`foo ...$opts`)
+    ///
+    /// Each field of the record acts as a named argument: the field name must match one of
+    /// the signature's flags, and the field value must fit the flag's value type. Imprecisely
+    /// typed spreads are accepted as-is; mistakes surface at runtime.
+    fn typecheck_spread(
+        &mut self,
+        spread_id: NodeId,
+        inner: NodeId,
+        flag_params: &[(&[u8], NodeId)],
+    ) {
+        let inner_ty = self.typecheck_expr(inner, TOP_TYPE);
+        self.set_node_type_id(spread_id, NONE_TYPE);
+
+        let Type::Record(record_id) = self.types[inner_ty.0] else {
+            if !matches!(
+                self.types[inner_ty.0],
+                Type::Any | Type::Unknown | Type::Top | Type::Bottom | Type::Error | Type::Var(_)
+            ) {
                 self.error(
-                    format!("Expected {} argument(s), got {}", params.len(), num_args),
-                    node_id,
+                    format!(
+                        "can only spread a record into flags, got {}",
+                        self.type_to_string(inner_ty)
+                    ),
+                    inner,
                 );
             }
-            for (param, arg) in params.iter().zip(&parts[num_name_parts..]) {
-                let expected = self.type_id_of(*param);
-                let expected = self.subst(expected, &type_substs);
-                if matches!(self.compiler.ast_nodes[arg.0], AstNode::Name) {
-                    self.set_node_type_id(*arg, STRING_TYPE);
-                    if !self.constrain_subtype(STRING_TYPE, expected) {
-                        self.error(
-                            format!("Expected {}, got string", self.type_to_string(expected)),
-                            *arg,
-                        );
-                    }
-                } else {
-                    self.typecheck_expr(*arg, expected);
-                }
-            }
-            if num_args > params.len() {
-                // Typecheck extra arguments too
-                for arg in &parts[num_name_parts + params.len()..] {
-                    if matches!(self.compiler.ast_nodes[arg.0], AstNode::Name) {
-                        self.set_node_type_id(*arg, STRING_TYPE);
-                    } else {
-                        self.typecheck_expr(*arg, TOP_TYPE);
-                    }
-                }
+            return;
+        };
+
+        let fields = self.record_types[record_id.0].clone();
+        for (field, field_ty) in fields {
+            let field_name = self.compiler.get_span_contents(field);
+            let Some((_, param)) = flag_params.iter().find(|(flag, _)| *flag == field_name)
+            else {
+                self.error(
+                    format!(
+                        "unknown flag '{}' in record spread",
+                        String::from_utf8_lossy(field_name)
+                    ),
+                    field,
+                );
+                continue;
+            };
+
+            let expected = self.type_id_of(*param);
+            if !self.is_subtype(field_ty, expected) {
+                let message = self.describe_mismatch(expected, field_ty);
+                self.error(message, field);
             }
+        }
+    }
 
-            // TODO base this on pipeline input type
-            let out_types = self.decl_types[decl_id.0]
-                .clone()
-                .iter()
-                .map(|io| self.subst(io.out_type, &type_substs))
-                .collect();
-            self.create_oneof(out_types)
-        } else {
-            // external call
-            for part in &parts[1..] {
-                if matches!(self.compiler.ast_nodes[part.0], AstNode::Name) {
-                    self.set_node_type_id(*part, STRING_TYPE);
-                } else {
-                    self.typecheck_expr(*part, TOP_TYPE);
+    /// Whether `into` can convert a value of the given type to the target type
+    fn can_convert(&self, from: TypeId, target: TypeId) -> bool {
+        if from == target {
+            return true;
+        }
+        // imprecise types convert silently; a mismatch surfaces at runtime
+        if matches!(
+            self.types[from.0],
+            Type::Any | Type::Unknown | Type::Top | Type::Bottom | Type::Error | Type::Var(_)
+        ) {
+            return true;
+        }
+
+        match target {
+            // structured values have no canonical string form
+            STRING_TYPE => !matches!(
+                self.types[from.0],
+                Type::Closure | Type::Record(_) | Type::BuiltinRecord(_) | Type::List(_) | Type::Stream(_)
+            ),
+            INT_TYPE => matches!(from, STRING_TYPE | FLOAT_TYPE | BOOL_TYPE | NUMBER_TYPE | BINARY_TYPE),
+            FLOAT_TYPE => matches!(from, STRING_TYPE | INT_TYPE | NUMBER_TYPE),
+            BOOL_TYPE => matches!(from, STRING_TYPE | INT_TYPE),
+            BINARY_TYPE => matches!(from, STRING_TYPE | INT_TYPE | BOOL_TYPE),
+            _ => false,
+        }
+    }
+
+    /// Typecheck the `catch` arm of a `try`, binding the closure's error parameter to the
+    /// structured error value type. Returns the output type of the catch.
+    fn typecheck_catch(&mut self, catch_id: NodeId) -> TypeId {
+        let AstNode::Closure { params, block } = self.compiler.ast_nodes[catch_id.0] else {
+            // `catch` not followed by a closure is a parse error; typecheck it as usual
+            return self.typecheck_expr(catch_id, TOP_TYPE);
+        };
+
+        if let Some(params_id) = params {
+            self.typecheck_node(params_id);
+
+            // bind the error parameter to the error value type, unless the user annotated it
+            // explicitly
+            let AstNode::Params(ref param_nodes) = self.compiler.ast_nodes[params_id.0] else {
+                panic!("internal error: expected params");
+            };
+            if let Some(param_id) = param_nodes.first().copied() {
+                let AstNode::Param { name, ty, .. } = self.compiler.ast_nodes[param_id.0] else {
+                    panic!("internal error: expected param");
+                };
+                if ty.is_none() {
+                    let var_id = self
+                        .compiler
+                        .var_resolution
+                        .get(&name)
+                        .expect("missing resolved variable");
+                    self.variable_types[var_id.0] = ERROR_VALUE_TYPE;
+                    self.set_node_type_id(param_id, ERROR_VALUE_TYPE);
                 }
             }
-
-            BYTE_STREAM_TYPE
         }
+
+        let body_type = self.typecheck_block(block, TOP_TYPE);
+        self.set_node_type_id(catch_id, CLOSURE_TYPE);
+
+        body_type
     }
 
     fn typecheck_let(
@@ -945,7 +2045,8 @@ impl<'a> Typechecker<'a> {
             self.typecheck_expr(initializer, ty_id);
             ty_id
         } else {
-            self.typecheck_expr(initializer, TOP_TYPE)
+            let inferred = self.typecheck_expr(initializer, TOP_TYPE);
+            self.apply_annotation_comment(node_id, inferred)
         };
 
         let var_id = self
@@ -959,6 +2060,66 @@ impl<'a> Typechecker<'a> {
         self.set_node_type_id(node_id, NONE_TYPE);
     }
 
+    /// Apply a trailing `# type: <name>` comment on the statement's line, if present
+    ///
+    /// A fallback for positions where inline type syntax is not available: the annotated
+    /// type is asserted against the inferred one and, when compatible, becomes the
+    /// variable's type. A mismatch or an unknown type name warns and keeps the inferred
+    /// type.
+    fn apply_annotation_comment(&mut self, node_id: NodeId, inferred: TypeId) -> TypeId {
+        let span = self.compiler.get_span(node_id);
+        let source = &self.compiler.source;
+        let line_end = source[span.end..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(source.len(), |pos| span.end + pos);
+
+        let Some(comment) = source[span.end..line_end].trim_ascii().strip_prefix(b"#") else {
+            return inferred;
+        };
+        let Some(name) = comment.trim_ascii().strip_prefix(b"type:") else {
+            return inferred;
+        };
+        let name = name.trim_ascii();
+
+        let annotated = match name {
+            b"any" => ANY_TYPE,
+            b"number" => NUMBER_TYPE,
+            b"nothing" => NOTHING_TYPE,
+            b"int" => INT_TYPE,
+            b"float" => FLOAT_TYPE,
+            b"bool" => BOOL_TYPE,
+            b"string" => STRING_TYPE,
+            b"binary" => BINARY_TYPE,
+            b"closure" => CLOSURE_TYPE,
+            b"list" => LIST_ANY_TYPE,
+            _ => {
+                self.warning(
+                    format!(
+                        "unknown type `{}` in annotation comment",
+                        String::from_utf8_lossy(name)
+                    ),
+                    node_id,
+                );
+                return inferred;
+            }
+        };
+
+        if self.is_subtype(inferred, annotated) {
+            annotated
+        } else {
+            self.warning(
+                format!(
+                    "value of type {} does not match its annotation comment ({})",
+                    self.type_to_string(inferred),
+                    self.type_to_string(annotated)
+                ),
+                node_id,
+            );
+            inferred
+        }
+    }
+
     fn typecheck_type(&mut self, node_id: NodeId) -> TypeId {
         let ty_id = match self.compiler.ast_nodes[node_id.0] {
             AstNode::Type {
@@ -976,7 +2137,7 @@ impl<'a> Typechecker<'a> {
                 let mut fields = field_nodes
                     .iter()
                     .map(|field| {
-                        let AstNode::Param { name, ty } = self.compiler.get_node(*field) else {
+                        let AstNode::Param { name, ty, .. } = self.compiler.get_node(*field) else {
                             panic!("internal error: record field isn't Param");
                         };
                         let ty_id = match ty {
@@ -1130,6 +2291,8 @@ impl<'a> Typechecker<'a> {
             | Type::Bool
             | Type::String
             | Type::Binary
+            | Type::BuiltinRecord(_)
+            | Type::Custom(_)
             | Type::Var(_) => ty_id,
             Type::Closure => todo!(),
             Type::List(elem_ty) => {
@@ -1148,6 +2311,14 @@ impl<'a> Typechecker<'a> {
                     self.push_type(Type::Stream(new_elem))
                 }
             }
+            Type::Range(elem_ty) => {
+                let new_elem = self.subst(elem_ty, substs);
+                if elem_ty == new_elem {
+                    ty_id
+                } else {
+                    self.push_type(Type::Range(new_elem))
+                }
+            }
             Type::Record(record_type_id) => {
                 let mut fields = self.record_types[record_type_id.0].clone();
                 for (_, ty) in fields.iter_mut() {
@@ -1220,7 +2391,8 @@ impl<'a> Typechecker<'a> {
             (_, Type::Top | Type::Any | Type::Unknown) => true,
             (Type::Bottom | Type::Any | Type::Unknown, _) => true,
             (Type::Int | Type::Float | Type::Number, Type::Number) => true,
-            (Type::List(inner_sub), Type::List(inner_supe)) => {
+            (Type::List(inner_sub), Type::List(inner_supe))
+            | (Type::Range(inner_sub), Type::Range(inner_supe)) => {
                 self.constrain_subtype(inner_sub, inner_supe)
             }
             (Type::Record(sub_rec_id), Type::Record(supe_rec_id)) => {
@@ -1322,7 +2494,8 @@ impl<'a> Typechecker<'a> {
             (_, Type::Top | Type::Any | Type::Unknown) => true,
             (Type::Bottom | Type::Any | Type::Unknown, _) => true,
             (Type::Int | Type::Float | Type::Number, Type::Number) => true,
-            (Type::List(inner_sub), Type::List(inner_supe)) => {
+            (Type::List(inner_sub), Type::List(inner_supe))
+            | (Type::Range(inner_sub), Type::Range(inner_supe)) => {
                 self.is_subtype(inner_sub, inner_supe)
             }
             (Type::Record(sub_rec_id), Type::Record(supe_rec_id)) => {
@@ -1399,6 +2572,8 @@ impl<'a> Typechecker<'a> {
             | Type::Bool
             | Type::String
             | Type::Binary
+            | Type::BuiltinRecord(_)
+            | Type::Custom(_)
             | Type::Ref(_) => ty_id,
             Type::Closure => ty_id,
             Type::List(inner_ty) => {
@@ -1417,6 +2592,14 @@ impl<'a> Typechecker<'a> {
                     self.push_type(Type::Stream(new_inner))
                 }
             }
+            Type::Range(inner_ty) => {
+                let new_inner = self.eliminate_type_vars(inner_ty, max_var, use_lower);
+                if inner_ty == new_inner {
+                    ty_id
+                } else {
+                    self.push_type(Type::Range(new_inner))
+                }
+            }
             Type::Record(record_type_id) => {
                 let mut changed = false;
                 let mut fields = self.record_types[record_type_id.0].clone();
@@ -1469,95 +2652,83 @@ impl<'a> Typechecker<'a> {
     }
 
     fn type_to_string(&self, type_id: TypeId) -> String {
-        let ty = &self.types[type_id.0];
+        render_type(
+            type_id,
+            &self.types,
+            &self.record_types,
+            &self.oneof_types,
+            &self.allof_types,
+            &self.type_vars,
+            self.compiler,
+        )
+    }
 
-        match ty {
-            Type::Unknown => "unknown".to_string(),
-            Type::Forbidden => "forbidden".to_string(),
-            Type::None => "()".to_string(),
-            Type::Error => "error".to_string(),
-            Type::Top => "top".to_string(),
-            Type::Bottom => "bottom".to_string(),
-            Type::Any => "any".to_string(),
-            Type::Number => "number".to_string(),
-            Type::Nothing => "nothing".to_string(),
-            Type::Int => "int".to_string(),
-            Type::Float => "float".to_string(),
-            Type::Bool => "bool".to_string(),
-            Type::Binary => "binary".to_string(),
-            Type::String => "string".to_string(),
-            Type::Closure => "closure".to_string(),
-            Type::List(subtype_id) => {
-                format!("list<{}>", self.type_to_string(*subtype_id))
-            }
-            Type::Stream(subtype_id) => {
-                format!("stream<{}>", self.type_to_string(*subtype_id))
-            }
-            Type::Record(id) => {
-                let mut fmt = "record<".to_string();
-                let types = &self.record_types[id.0];
-                for (name, ty) in types {
-                    fmt += &String::from_utf8_lossy(self.compiler.get_span_contents(*name));
-                    fmt += ": ";
-                    fmt += &self.type_to_string(*ty);
-                    fmt += ", ";
-                }
-                if !types.is_empty() {
-                    fmt.pop();
-                    fmt.pop();
-                }
-                fmt.push('>');
-                fmt
-            }
-            Type::OneOf(id) => {
-                let mut fmt = "oneof<".to_string();
-                let mut types: Vec<_> = self.oneof_types[id.0]
-                    .iter()
-                    .map(|ty| self.type_to_string(*ty) + ", ")
-                    .collect();
-                types.sort();
-                for ty in &types {
-                    fmt += ty;
+    /// Infer a partial record shape for weakly-typed variables from their member accesses
+    ///
+    /// For each variable whose type is any/unknown and that is accessed via member access
+    /// (e.g., $x.a, $x.b), synthesize a record type with the accessed fields typed as any.
+    /// Usages conflicting with a record shape (iterating the variable, or a non-name field
+    /// access) downgrade the inferred type to any. Intended for hover/completion tooling; it
+    /// does not change the checked node types.
+    pub fn infer_record_from_usage(&mut self) -> HashMap<VarId, TypeId> {
+        let mut accesses: HashMap<VarId, Vec<NodeId>> = HashMap::new();
+        let mut conflicted: HashSet<VarId> = HashSet::new();
+
+        for node in self.compiler.ast_nodes.iter() {
+            match node {
+                AstNode::MemberAccess { target, field, .. } => {
+                    if !matches!(self.compiler.ast_nodes[target.0], AstNode::Variable) {
+                        continue;
+                    }
+                    let Some(var_id) = self.compiler.var_resolution.get(target) else {
+                        continue;
+                    };
+                    if matches!(self.compiler.ast_nodes[field.0], AstNode::Name) {
+                        accesses.entry(*var_id).or_default().push(*field);
+                    } else {
+                        // dynamic access, e.g. indexing; no record shape can be derived
+                        conflicted.insert(*var_id);
+                    }
                 }
-                if !types.is_empty() {
-                    fmt.pop();
-                    fmt.pop();
+                AstNode::For { range, .. } => {
+                    // iterating a variable implies a list, conflicting with a record shape
+                    if let Some(var_id) = self.compiler.var_resolution.get(range) {
+                        conflicted.insert(*var_id);
+                    }
                 }
-                fmt.push('>');
-                fmt
+                _ => {}
             }
-            Type::AllOf(id) => {
-                let mut fmt = "allof<".to_string();
-                let mut types: Vec<_> = self.allof_types[id.0]
-                    .iter()
-                    .map(|ty| self.type_to_string(*ty) + ", ")
-                    .collect();
-                types.sort();
-                for ty in &types {
-                    fmt += ty;
-                }
-                if !types.is_empty() {
-                    fmt.pop();
-                    fmt.pop();
-                }
-                fmt.push('>');
-                fmt
-            }
-            Type::Ref(type_decl_id) => match self.compiler.type_decls[type_decl_id.0] {
-                TypeDecl::Param(name_node) => {
-                    String::from_utf8_lossy(self.compiler.get_span_contents(name_node)).to_string()
-                }
-            },
-            Type::Var(type_var_id) => {
-                let var = &self.type_vars[type_var_id.0];
-                format!(
-                    "{} <: '{} <: {}",
-                    self.type_to_string(var.lower_bound),
-                    type_var_id.0,
-                    self.type_to_string(var.upper_bound)
-                )
+        }
+
+        let mut inferred = HashMap::new();
+        for (var_id, field_nodes) in accesses {
+            let var_type = self.variable_types[var_id.0];
+            if var_type != ANY_TYPE && var_type != UNKNOWN_TYPE {
+                // the variable already has a concrete type; nothing to infer
+                continue;
+            }
+
+            if conflicted.contains(&var_id) {
+                inferred.insert(var_id, ANY_TYPE);
+                continue;
+            }
+
+            let mut seen_names = HashSet::new();
+            let mut fields = Vec::new();
+            for field_node in field_nodes {
+                let name = self.compiler.get_span_contents(field_node);
+                if seen_names.insert(name) {
+                    fields.push((field_node, ANY_TYPE));
+                }
             }
+            fields.sort_by_cached_key(|(name, _)| self.compiler.get_span_contents(*name));
+
+            self.record_types.push(fields);
+            let ty_id = self.push_type(Type::Record(RecordTypeId(self.record_types.len() - 1)));
+            inferred.insert(var_id, ty_id);
         }
+
+        inferred
     }
 
     /// Check if one type can be cast to another type
@@ -1608,6 +2779,25 @@ impl<'a> Typechecker<'a> {
             message: msg.into(),
             node_id,
             severity: Severity::Error,
+            code: None,
+        })
+    }
+
+    fn note(&mut self, msg: impl Into<String>, node_id: NodeId) {
+        self.errors.push(SourceError {
+            message: msg.into(),
+            node_id,
+            severity: Severity::Note,
+            code: None,
+        })
+    }
+
+    fn warning(&mut self, msg: impl Into<String>, node_id: NodeId) {
+        self.errors.push(SourceError {
+            message: msg.into(),
+            node_id,
+            severity: Severity::Warning,
+            code: None,
         })
     }
 
@@ -1892,3 +3082,298 @@ impl<'a> Typechecker<'a> {
         self.create_oneof(inters)
     }
 }
+
+
+/// Render a type as a human-readable string using the given type tables
+///
+/// Shared between the typechecker, which owns the tables while checking, and the compiler,
+/// which retains them after [`Compiler::merge_types`].
+pub(crate) fn render_type(
+    type_id: TypeId,
+    types: &[Type],
+    record_types: &[Vec<(NodeId, TypeId)>],
+    oneof_types: &[HashSet<TypeId>],
+    allof_types: &[HashSet<TypeId>],
+    type_vars: &[TypeVar],
+    compiler: &Compiler,
+) -> String {
+    let render = |id: TypeId| {
+        render_type(
+            id,
+            types,
+            record_types,
+            oneof_types,
+            allof_types,
+            type_vars,
+            compiler,
+        )
+    };
+
+    let ty = &types[type_id.0];
+
+    match ty {
+        Type::Unknown => "unknown".to_string(),
+        Type::Forbidden => "forbidden".to_string(),
+        Type::None => "()".to_string(),
+        Type::Error => "error".to_string(),
+        Type::Top => "top".to_string(),
+        Type::Bottom => "bottom".to_string(),
+        Type::Any => "any".to_string(),
+        Type::Number => "number".to_string(),
+        Type::Nothing => "nothing".to_string(),
+        Type::Int => "int".to_string(),
+        Type::Float => "float".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Binary => "binary".to_string(),
+        Type::String => "string".to_string(),
+        Type::Closure => "closure".to_string(),
+        Type::List(subtype_id) => {
+            format!("list<{}>", render(*subtype_id))
+        }
+        Type::Stream(subtype_id) => {
+            format!("stream<{}>", render(*subtype_id))
+        }
+        Type::Range(subtype_id) => {
+            format!("range<{}>", render(*subtype_id))
+        }
+        Type::BuiltinRecord(BuiltinRecord::ErrorValue) => "error".to_string(),
+        Type::Custom(custom_id) => {
+            // render the registered suffix so that error messages stay readable
+            match compiler
+                .custom_suffixes
+                .iter()
+                .find(|(_, type_id)| type_id == custom_id)
+            {
+                Some((suffix, _)) => {
+                    format!("custom<{}>", String::from_utf8_lossy(suffix))
+                }
+                None => format!("custom<{}>", custom_id.0),
+            }
+        }
+        Type::BuiltinRecord(rec) => {
+            let mut fmt = "record<".to_string();
+            for (name, ty) in rec.fields() {
+                fmt += name;
+                fmt += ": ";
+                fmt += &render(*ty);
+                fmt += ", ";
+            }
+            fmt.pop();
+            fmt.pop();
+            fmt.push('>');
+            fmt
+        }
+        Type::Record(id) => {
+            let mut fmt = "record<".to_string();
+            let types = &record_types[id.0];
+            for (name, ty) in types {
+                fmt += &String::from_utf8_lossy(compiler.get_span_contents(*name));
+                fmt += ": ";
+                fmt += &render(*ty);
+                fmt += ", ";
+            }
+            if !types.is_empty() {
+                fmt.pop();
+                fmt.pop();
+            }
+            fmt.push('>');
+            fmt
+        }
+        Type::OneOf(id) => {
+            // unions render as `A | B`, with members sorted for a stable output
+            let mut types: Vec<_> = oneof_types[id.0]
+                .iter()
+                .map(|ty| render(*ty))
+                .collect();
+            types.sort();
+            types.join(" | ")
+        }
+        Type::AllOf(id) => {
+            let mut fmt = "allof<".to_string();
+            let mut types: Vec<_> = allof_types[id.0]
+                .iter()
+                .map(|ty| render(*ty) + ", ")
+                .collect();
+            types.sort();
+            for ty in &types {
+                fmt += ty;
+            }
+            if !types.is_empty() {
+                fmt.pop();
+                fmt.pop();
+            }
+            fmt.push('>');
+            fmt
+        }
+        Type::Ref(type_decl_id) => match compiler.type_decls[type_decl_id.0] {
+            TypeDecl::Param(name_node) => {
+                String::from_utf8_lossy(compiler.get_span_contents(name_node)).to_string()
+            }
+        },
+        Type::Var(type_var_id) => {
+            let var = &type_vars[type_var_id.0];
+            format!(
+                "{} <: '{} <: {}",
+                render(var.lower_bound),
+                type_var_id.0,
+                render(var.upper_bound)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::parser::{AstNode, NodeId};
+    use crate::typechecker::{
+        Type, TypeVar, TypeVarId, Typechecker, ANY_TYPE, FLOAT_TYPE, INT_TYPE, NUMBER_TYPE,
+        STRING_TYPE,
+    };
+    use std::collections::HashSet;
+
+    /// Lex, parse and resolve a source, returning the compiler ready for typechecking
+    fn prepare(source: &[u8]) -> Compiler {
+        let mut compiler = Compiler::new();
+        let span_offset = compiler.span_offset();
+        compiler.add_file("<test>", source);
+
+        let (tokens, err) = lex(source, span_offset);
+        assert!(err.is_ok());
+
+        let parser = Parser::new(compiler, tokens);
+        let mut compiler = parser.parse();
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        compiler
+    }
+
+    #[test]
+    fn union_subtyping_relates_members_and_supertypes() {
+        let compiler = prepare(b"1\n");
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        let mut members = HashSet::new();
+        members.insert(INT_TYPE);
+        members.insert(STRING_TYPE);
+        let int_or_string = typechecker.create_oneof(members);
+        assert_eq!(typechecker.type_to_string(int_or_string), "int | string");
+
+        // a member is a subtype of the union
+        assert!(typechecker.is_subtype(INT_TYPE, int_or_string));
+        assert!(!typechecker.is_subtype(FLOAT_TYPE, int_or_string));
+
+        // the union is a subtype of S only when every member is
+        assert!(typechecker.is_subtype(int_or_string, ANY_TYPE));
+        assert!(!typechecker.is_subtype(int_or_string, INT_TYPE));
+        assert!(!typechecker.is_subtype(int_or_string, NUMBER_TYPE));
+    }
+
+    #[test]
+    fn describe_mismatch_points_at_the_differing_part() {
+        let compiler = prepare(b"{x: 1}\n{x: \"s\"}\n");
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        assert_eq!(
+            typechecker.describe_mismatch(INT_TYPE, STRING_TYPE),
+            "expected `int`, found `string`"
+        );
+
+        let list_int = typechecker.push_type(Type::List(INT_TYPE));
+        let list_string = typechecker.push_type(Type::List(STRING_TYPE));
+        assert_eq!(
+            typechecker.describe_mismatch(list_int, list_string),
+            "expected `list<int>`, found `list<string>`: list element: \
+             expected `int`, found `string`"
+        );
+
+        let records: Vec<_> = compiler
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, AstNode::Record { .. }))
+            .map(|(idx, _)| typechecker.type_id_of(NodeId(idx)))
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            typechecker.describe_mismatch(records[0], records[1]),
+            "expected `record<x: int>`, found `record<x: string>`: field `x`: \
+             expected `int`, found `string`"
+        );
+    }
+
+    #[test]
+    fn canonicalize_types_solves_append_vars_to_concrete_lists() {
+        let compiler = prepare(b"[1] ++ [2]\n");
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        // the append's result variable is solved; its element type is concrete
+        let result = typechecker.type_id_of(NodeId(compiler.ast_nodes.len() - 1));
+        assert_eq!(typechecker.type_to_string(result), "list<int>");
+        for type_id in &typechecker.node_types {
+            assert!(!matches!(typechecker.types[type_id.0], Type::Var(_)));
+        }
+    }
+
+    #[test]
+    fn canonicalize_types_defaults_irreducibly_open_vars_to_any() {
+        let compiler = prepare(b"1\n");
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        // simulate a variable inference could not solve: its bound is itself still open
+        let var_id = TypeVarId(typechecker.type_vars.len());
+        let bound = typechecker.push_type(Type::Var(var_id));
+        typechecker.type_vars.push(TypeVar {
+            lower_bound: bound,
+            upper_bound: bound,
+        });
+        typechecker.node_types[0] = bound;
+
+        typechecker.canonicalize_types(true);
+
+        assert!(matches!(typechecker.type_of(NodeId(0)), Type::Any));
+        assert!(typechecker
+            .errors
+            .iter()
+            .any(|error| error.message.contains("cannot infer")));
+    }
+
+    #[test]
+    fn infer_record_from_field_accesses() {
+        let compiler = prepare(b"def f [x] { $x.a + $x.b }\n");
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        let inferred = typechecker.infer_record_from_usage();
+        assert_eq!(inferred.len(), 1);
+
+        let ty_id = inferred.values().next().expect("one inferred variable");
+        assert_eq!(
+            typechecker.type_to_string(*ty_id),
+            "record<a: any, b: any>"
+        );
+    }
+
+    #[test]
+    fn conflicting_usage_downgrades_to_any() {
+        let compiler = prepare(b"def f [x] { for y in $x { }\n$x.a }\n");
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        let inferred = typechecker.infer_record_from_usage();
+        assert_eq!(inferred.len(), 1);
+
+        let ty_id = inferred.values().next().expect("one inferred variable");
+        assert_eq!(*ty_id, ANY_TYPE);
+    }
+}